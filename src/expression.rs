@@ -0,0 +1,224 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::slice::Iter;
+
+use crate::lexer::{check_type_suffix, is_symbol_reference, parse_literal, Token};
+
+#[derive(Clone)]
+pub enum Expression {
+    Number(i64),
+    Symbol(String),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Function(&'static str, Box<Expression>), // hi() and lo()
+}
+
+pub type Tokens<'a> = Peekable<Iter<'a, Token>>;
+
+// Binary operators grouped from the lowest to the highest precedence
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const UNARY_PRECEDENCE: usize = BINARY_OPERATORS.len();
+
+pub fn is_binary_operator(token: &Token) -> bool {
+    matches!(token, Token::Punct(punct) if BINARY_OPERATORS.iter().any(|level| level.contains(&punct.trim_start())))
+}
+
+pub fn parse_expression(tokens: &mut Tokens) -> Result<Expression, String> {
+    parse_binary(tokens, 0)
+}
+
+fn parse_binary(tokens: &mut Tokens, precedence: usize) -> Result<Expression, String> {
+    if precedence == UNARY_PRECEDENCE {
+        return parse_unary(tokens);
+    }
+
+    let mut left = parse_binary(tokens, precedence + 1)?;
+
+    // The spaced signs of the lexer are the usual operators inside an expression
+    while let Some(Token::Punct(operator)) = tokens.peek() {
+        let operator = operator.trim_start();
+
+        if !BINARY_OPERATORS[precedence].contains(&operator) {
+            break;
+        }
+        tokens.next();
+
+        let right = parse_binary(tokens, precedence + 1)?;
+        left = Expression::Binary(operator, Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+}
+
+fn parse_unary(tokens: &mut Tokens) -> Result<Expression, String> {
+    match tokens.next() {
+        Some(Token::Punct("+" | " +")) => parse_unary(tokens),
+
+        Some(Token::Punct(operator @ ("-" | " -" | "~" | "!"))) => Ok(Expression::Unary(
+            operator.trim_start(),
+            Box::new(parse_unary(tokens)?),
+        )),
+
+        Some(Token::Punct("(")) => parse_parenthesized(tokens),
+
+        Some(Token::Word(word)) => {
+            if let Some(n) = parse_literal(word) {
+                match i64::try_from(n) {
+                    Ok(n) => Ok(Expression::Number(n)),
+                    Err(_) => Err(format!("Number '{word}' is too large")),
+                }
+            } else if let Some(function) = ["hi", "lo"].into_iter().find(|f| f == word) {
+                if tokens.next() != Some(&Token::Punct("(")) {
+                    return Err(format!("Missing '(' after {function}"));
+                }
+
+                Ok(Expression::Function(
+                    function,
+                    Box::new(parse_parenthesized(tokens)?),
+                ))
+            } else if is_symbol_reference(word) {
+                Ok(Expression::Symbol(word.clone()))
+            } else {
+                check_type_suffix(word)?;
+                Err(format!("Can't use '{word}' in an expression"))
+            }
+        }
+
+        Some(token) => Err(format!("Unexpected '{token}'")),
+
+        None => Err("Missing value at the end of the expression".to_owned()),
+    }
+}
+
+// Parses the rest of an expression whose opening parenthesis is already consumed
+fn parse_parenthesized(tokens: &mut Tokens) -> Result<Expression, String> {
+    let expression = parse_expression(tokens)?;
+
+    if tokens.next() == Some(&Token::Punct(")")) {
+        Ok(expression)
+    } else {
+        Err(format!("Missing ')' after '{expression}'"))
+    }
+}
+
+impl Expression {
+    // symbol_value gives the value of the symbols used by the expression
+    pub fn evaluate(
+        &self,
+        symbol_value: &mut dyn FnMut(&str) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        match self {
+            Expression::Number(n) => Ok(*n),
+
+            Expression::Symbol(name) => symbol_value(name),
+
+            Expression::Unary(operator, operand) => {
+                let value = operand.evaluate(symbol_value)?;
+
+                match *operator {
+                    "-" => value
+                        .checked_neg()
+                        .ok_or_else(|| format!("Overflow in '{self}'")),
+                    "!" => Ok((value == 0) as i64),
+                    _ => Ok(!value),
+                }
+            }
+
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(symbol_value)?;
+                let right = right.evaluate(symbol_value)?;
+
+                let result = match *operator {
+                    "+" => left.checked_add(right),
+                    "-" => left.checked_sub(right),
+                    "*" => left.checked_mul(right),
+                    "/" | "%" if right == 0 => {
+                        return Err(format!("Division by zero in '{self}'"));
+                    }
+                    "/" => left.checked_div(right),
+                    "%" => left.checked_rem(right),
+                    "<<" | ">>" if !(0..64).contains(&right) => {
+                        return Err(format!("Shift by {right} bits in '{self}'"));
+                    }
+                    "<<" => Some(left << right).filter(|shifted| shifted >> right == left),
+                    ">>" => Some(left >> right),
+                    "&" => Some(left & right),
+                    "|" => Some(left | right),
+                    // Comparisons and logical operators give 1 for true and 0 for false
+                    "==" => Some((left == right) as i64),
+                    "!=" => Some((left != right) as i64),
+                    "<" => Some((left < right) as i64),
+                    "<=" => Some((left <= right) as i64),
+                    ">" => Some((left > right) as i64),
+                    ">=" => Some((left >= right) as i64),
+                    "&&" => Some((left != 0 && right != 0) as i64),
+                    "||" => Some((left != 0 || right != 0) as i64),
+                    _ => Some(left ^ right),
+                };
+
+                result.ok_or_else(|| format!("Overflow in '{self}'"))
+            }
+
+            Expression::Function(function, argument) => {
+                let value = argument.evaluate(symbol_value)?;
+
+                match *function {
+                    "hi" => Ok((value >> 8) & 0xFF),
+                    _ => Ok(value & 0xFF),
+                }
+            }
+        }
+    }
+
+    fn precedence(&self) -> usize {
+        match self {
+            Expression::Binary(operator, _, _) => BINARY_OPERATORS
+                .iter()
+                .position(|level| level.contains(operator))
+                .unwrap(),
+            _ => UNARY_PRECEDENCE,
+        }
+    }
+
+    // Writes a sub-expression, in parentheses when it binds less tightly than its parent
+    fn fmt_operand(&self, f: &mut fmt::Formatter, precedence: usize) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Number(n) => write!(f, "{n}"),
+            Expression::Symbol(name) => write!(f, "{name}"),
+            Expression::Unary(operator, operand) => {
+                write!(f, "{operator}")?;
+                operand.fmt_operand(f, UNARY_PRECEDENCE)
+            }
+            Expression::Binary(operator, left, right) => {
+                let precedence = self.precedence();
+
+                left.fmt_operand(f, precedence)?;
+                write!(f, " {operator} ")?;
+                right.fmt_operand(f, precedence + 1)
+            }
+            Expression::Function(function, argument) => write!(f, "{function}({argument})"),
+        }
+    }
+}
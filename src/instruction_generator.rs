@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[derive(Clone, Copy)]
 pub enum Register {
     V(u8), // general purpose register
     I,     // address register
@@ -6,6 +9,7 @@ pub enum Register {
 }
 
 pub enum Instruction {
+    Sys,
     Cls,
     Ret,
     Jmp,
@@ -33,11 +37,105 @@ pub enum Instruction {
     Ldn,
 }
 
+#[derive(Clone, Copy)]
 pub enum Parameter {
     Register(Register), // register
     Address(u16),       // 12 bits value
     Byte(u8),           // 8 bits value
     Nibble(u8),         // 4 bits value
+    Number(i64),        // untyped value, sized by the operand slot it is used in
+}
+
+// Native mnemonic, used by listings
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = match self {
+            Instruction::Sys => "SYS",
+            Instruction::Cls => "CLS",
+            Instruction::Ret => "RET",
+            Instruction::Jmp => "JMP",
+            Instruction::Call => "CALL",
+            Instruction::Seq => "SEQ",
+            Instruction::Sne => "SNE",
+            Instruction::Ld => "LD",
+            Instruction::Add => "ADD",
+            Instruction::Or => "OR",
+            Instruction::And => "AND",
+            Instruction::Xor => "XOR",
+            Instruction::Sub => "SUB",
+            Instruction::Shr => "SHR",
+            Instruction::Subn => "SUBN",
+            Instruction::Shl => "SHL",
+            Instruction::Jmpo => "JMPO",
+            Instruction::Rnd => "RND",
+            Instruction::Drw => "DRW",
+            Instruction::Skp => "SKP",
+            Instruction::Sknp => "SKNP",
+            Instruction::Ldk => "LDK",
+            Instruction::Spr => "SPR",
+            Instruction::Bcd => "BCD",
+            Instruction::Stn => "STN",
+            Instruction::Ldn => "LDN",
+        };
+
+        write!(f, "{mnemonic}")
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Parameter::Register(Register::V(x)) => write!(f, "V{x:X}"),
+            Parameter::Register(Register::I) => write!(f, "I"),
+            Parameter::Register(Register::ST) => write!(f, "ST"),
+            Parameter::Register(Register::DT) => write!(f, "DT"),
+            Parameter::Address(nnn) => write!(f, "0x{nnn:03X}"),
+            Parameter::Byte(nn) => write!(f, "0x{nn:02X}"),
+            Parameter::Nibble(n) => write!(f, "{n}"),
+            Parameter::Number(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+// Reads a parameter used as the 12 bits nnn field of an opcode
+fn as_nnn(parameter: &Parameter) -> Result<Option<u16>, String> {
+    match *parameter {
+        Parameter::Address(nnn) => Ok(Some(nnn)),
+        Parameter::Number(n) if (0..0x1000).contains(&n) => Ok(Some(n as u16)),
+        Parameter::Number(n) => Err(format!("Value {n} doesn't fit in nnn (12 bits)")),
+        _ => Ok(None),
+    }
+}
+
+// Reads a parameter used as the 8 bits nn field of an opcode, negative values use two's complement
+fn as_nn(parameter: &Parameter) -> Result<Option<u8>, String> {
+    match *parameter {
+        Parameter::Byte(nn) => Ok(Some(nn)),
+        Parameter::Number(n) if (-0x80..0x100).contains(&n) => Ok(Some((n & 0xFF) as u8)),
+        Parameter::Number(n) => Err(format!("Value {n} doesn't fit in nn (8 bits)")),
+        _ => Ok(None),
+    }
+}
+
+// Reads a parameter used as the 4 bits n field of an opcode
+fn as_n(parameter: &Parameter) -> Result<Option<u8>, String> {
+    match *parameter {
+        Parameter::Nibble(n) => Ok(Some(n)),
+        Parameter::Number(n) if (0..0x10).contains(&n) => Ok(Some(n as u8)),
+        Parameter::Number(n) => Err(format!("Value {n} doesn't fit in n (4 bits)")),
+        _ => Ok(None),
+    }
+}
+
+fn generate_sys(parameters: &[Parameter]) -> Result<u16, String> {
+    if parameters.len() != 1 {
+        return Err("SYS takes one parameter".to_owned());
+    }
+
+    if let Some(nnn) = as_nnn(&parameters[0])? {
+        return Ok(nnn);
+    }
+    Err("SYS first parameter must be an Address".to_owned())
 }
 
 fn generate_cls(parameters: &[Parameter]) -> Result<u16, String> {
@@ -59,7 +157,7 @@ fn generate_jmp(parameters: &[Parameter]) -> Result<u16, String> {
         return Err("JMP takes one parameter".to_owned());
     }
 
-    if let Parameter::Address(nnn) = parameters[0] {
+    if let Some(nnn) = as_nnn(&parameters[0])? {
         return Ok(0x1 << 12 | nnn);
     }
     Err("JMP first parameter must be Address".to_owned())
@@ -70,7 +168,7 @@ fn generate_call(parameters: &[Parameter]) -> Result<u16, String> {
         return Err("CALL takes one parameter".to_owned());
     }
 
-    if let Parameter::Address(nnn) = parameters[0] {
+    if let Some(nnn) = as_nnn(&parameters[0])? {
         return Ok(0x2 << 12 | nnn);
     }
     Err("CALL first parameter must be Address".to_owned())
@@ -84,7 +182,7 @@ fn generate_seq(parameters: &[Parameter]) -> Result<u16, String> {
     if let Parameter::Register(Register::V(x)) = parameters[0] {
         if let Parameter::Register(Register::V(y)) = parameters[1] {
             return Ok(0x5 << 12 | (x as u16) << 8 | (y as u16) << 4);
-        } else if let Parameter::Byte(nn) = parameters[1] {
+        } else if let Some(nn) = as_nn(&parameters[1])? {
             return Ok(0x3 << 12 | (x as u16) << 8 | nn as u16);
         }
         return Err("SEQ second parameter must be V[n] or byte".to_owned());
@@ -100,7 +198,7 @@ fn generate_sne(parameters: &[Parameter]) -> Result<u16, String> {
     if let Parameter::Register(Register::V(x)) = parameters[0] {
         if let Parameter::Register(Register::V(y)) = parameters[1] {
             return Ok(0x9 << 12 | (x as u16) << 8 | (y as u16) << 4);
-        } else if let Parameter::Byte(nn) = parameters[1] {
+        } else if let Some(nn) = as_nn(&parameters[1])? {
             return Ok(0x4 << 12 | (x as u16) << 8 | nn as u16);
         }
         return Err("SNE second parameter must be V[n] or byte".to_owned());
@@ -118,13 +216,13 @@ fn generate_ld(parameters: &[Parameter]) -> Result<u16, String> {
             return Ok(0x8 << 12 | (x as u16) << 8 | (y as u16) << 4);
         } else if let Parameter::Register(Register::DT) = parameters[1] {
             return Ok(0xF << 12 | (x as u16) << 8 | 0x07);
-        } else if let Parameter::Byte(nn) = parameters[1] {
+        } else if let Some(nn) = as_nn(&parameters[1])? {
             return Ok(0x6 << 12 | (x as u16) << 8 | nn as u16);
         }
 
         return Err("LD V[n] second parameter must be V[n], DT or Byte".to_owned());
     } else if let Parameter::Register(Register::I) = parameters[0] {
-        if let Parameter::Address(nnn) = parameters[1] {
+        if let Some(nnn) = as_nnn(&parameters[1])? {
             return Ok(0xA << 12 | nnn);
         }
         return Err("LD I second parameter must be Address".to_owned());
@@ -150,7 +248,7 @@ fn generate_add(parameters: &[Parameter]) -> Result<u16, String> {
     if let Parameter::Register(Register::V(x)) = parameters[0] {
         if let Parameter::Register(Register::V(y)) = parameters[1] {
             return Ok(0x8 << 12 | (x as u16) << 8 | (y as u16) << 4 | 0x4);
-        } else if let Parameter::Byte(nn) = parameters[1] {
+        } else if let Some(nn) = as_nn(&parameters[1])? {
             return Ok(0x7 << 12 | (x as u16) << 8 | nn as u16);
         }
         return Err("ADD V[n] second parameter must be V[n] or Byte".to_owned());
@@ -266,7 +364,7 @@ fn generate_jmpo(parameters: &[Parameter]) -> Result<u16, String> {
         return Err("JMPO takes one parameter".to_owned());
     }
 
-    if let Parameter::Address(nnn) = parameters[0] {
+    if let Some(nnn) = as_nnn(&parameters[0])? {
         return Ok(0xB << 12 | nnn);
     }
     Err("JMPO first parameter must be an Address".to_string())
@@ -278,7 +376,7 @@ fn generate_rnd(parameters: &[Parameter]) -> Result<u16, String> {
     }
 
     if let Parameter::Register(Register::V(x)) = parameters[0] {
-        if let Parameter::Byte(nn) = parameters[1] {
+        if let Some(nn) = as_nn(&parameters[1])? {
             return Ok(0xC << 12 | (x as u16) << 8 | nn as u16);
         }
         return Err("RND second parameter must be a Byte".to_string());
@@ -293,7 +391,7 @@ fn generate_drw(parameters: &[Parameter]) -> Result<u16, String> {
 
     if let Parameter::Register(Register::V(x)) = parameters[0] {
         if let Parameter::Register(Register::V(y)) = parameters[1] {
-            if let Parameter::Nibble(n) = parameters[2] {
+            if let Some(n) = as_n(&parameters[2])? {
                 return Ok(0xD << 12 | (x as u16) << 8 | (y as u16) << 4 | n as u16);
             }
             return Err("DRW third parameter must be a Nibble".to_string());
@@ -385,6 +483,7 @@ pub fn generate_instruction(
     parameters: &[Parameter],
 ) -> Result<u16, String> {
     match instruction {
+        Instruction::Sys => generate_sys(parameters),
         Instruction::Cls => generate_cls(parameters),
         Instruction::Ret => generate_ret(parameters),
         Instruction::Jmp => generate_jmp(parameters),
@@ -0,0 +1,200 @@
+use std::fmt;
+
+#[derive(Clone, PartialEq)]
+pub enum Token {
+    Word(String),        // mnemonic, directive, register, symbol or number literal
+    Str(String),         // text between double quotes
+    Punct(&'static str), // operator, parenthesis, bracket, comma or colon
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Str(text) => write!(f, "\"{text}\""),
+            Token::Punct(punct) => write!(f, "{}", punct.trim_start()),
+        }
+    }
+}
+
+// '-' or '+' with a space before and none after, following a value other than a V register or
+// the mnemonic : in 0x10 -0x20 it could be a subtraction or the sign of a second value, lists of
+// operands separated by spaces refuse it
+pub const SPACED_SIGNS: [&str; 2] = [" -", " +"];
+
+// Two characters punctuations come first so that they are matched before their first character
+const PUNCTUATIONS: [&str; 26] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%", "&",
+    "|", "^", "~", "(", ")", "[", "]", ",", ":",
+];
+
+// Splits a line into tokens and removes the comment
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::<Token>::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        // '%' starts a binary literal when it is stuck to the digits but not to a previous value
+        let binary_prefix = c == '%'
+            && line[start + 1..].starts_with(['0', '1'])
+            && !line[..start].ends_with(|c: char| is_word_char(c) || c == ')');
+
+        if c == '"' {
+            let Some(length) = line[start + 1..].find('"') else {
+                return Err("Unterminated string".to_owned());
+            };
+
+            tokens.push(Token::Str(line[start + 1..start + 1 + length].to_owned()));
+
+            while chars.next_if(|&(i, _)| i <= start + 1 + length).is_some() {}
+            continue;
+        } else if c == '\'' {
+            chars.next();
+
+            if !matches!((chars.next(), chars.next()), (Some(_), Some((_, '\'')))) {
+                return Err("Unterminated character literal".to_owned());
+            }
+        } else if is_word_char(c) || c == '$' || c == '#' || binary_prefix {
+            chars.next();
+        } else if let Some(punct) = PUNCTUATIONS.iter().find(|p| line[start..].starts_with(**p)) {
+            for _ in 0..punct.len() {
+                chars.next();
+            }
+
+            // The mnemonic, directive or macro name is the first word of the line or follows a
+            // label, like a V register it can't be part of an expression
+            let is_mnemonic = matches!(
+                &tokens[..],
+                [Token::Word(_)] | [_, Token::Punct(":"), Token::Word(_)]
+            );
+            let follows_value = match tokens.last() {
+                Some(Token::Word(word)) => !is_mnemonic && !is_v_register_name(word),
+                Some(Token::Str(_) | Token::Punct(")" | "]")) => true,
+                _ => false,
+            };
+            let is_spaced_sign = follows_value
+                && line[..start].ends_with(char::is_whitespace)
+                && line[start + 1..].starts_with(|c: char| !c.is_whitespace());
+
+            match SPACED_SIGNS.iter().find(|sign| sign[1..] == **punct) {
+                Some(sign) if is_spaced_sign => tokens.push(Token::Punct(sign)),
+                _ => tokens.push(Token::Punct(punct)),
+            }
+            continue;
+        } else {
+            return Err(format!("Unexpected character '{c}'"));
+        }
+
+        let mut end = line.len();
+
+        while let Some(&(i, c)) = chars.peek() {
+            if !is_word_char(c) {
+                end = i;
+                break;
+            }
+            chars.next();
+        }
+
+        tokens.push(Token::Word(line[start..end].to_owned()));
+    }
+
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// Symbols start with a letter or an underscore and can't be mistaken for a V register, they can
+// also contain '@' which is never part of a word, to name the local labels of macro expansions.
+// I, DT and ST are registers only as a whole operand of an instruction, elsewhere they can be
+// symbols like the loop variable i
+pub fn is_symbol_name(word: &str) -> bool {
+    let mut chars = word.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@')
+        && !is_v_register_name(word)
+}
+
+// Symbol name, local label (.loop) or name inside a scope or a label (sprites.ship, main.loop)
+pub fn is_symbol_reference(word: &str) -> bool {
+    word.strip_prefix('.')
+        .unwrap_or(word)
+        .split('.')
+        .all(is_symbol_name)
+}
+
+// Case-insensitive
+pub fn is_register_name(word: &str) -> bool {
+    matches!(word.to_ascii_uppercase().as_str(), "I" | "DT" | "ST") || is_v_register_name(word)
+}
+
+// Case-insensitive, V0 to V15 or VA to VF
+pub fn is_v_register_name(word: &str) -> bool {
+    word.to_ascii_uppercase()
+        .strip_prefix('V')
+        .is_some_and(|n_str| {
+            matches!(n_str, "A" | "B" | "C" | "D" | "E" | "F")
+                || n_str
+                    .parse::<u8>()
+                    .is_ok_and(|n| n < 16 && n.to_string() == n_str)
+        })
+}
+
+// Decimal, hexadecimal (0x1F, $1F, #1F), binary (0b0101, %0101) or character ('A') literal
+pub fn parse_number(word: &str) -> Option<u64> {
+    if let Some(hex_str) = word
+        .strip_prefix("0x")
+        .or_else(|| word.strip_prefix('$'))
+        .or_else(|| word.strip_prefix('#'))
+    {
+        u64::from_str_radix(hex_str, 16).ok()
+    } else if let Some(bin_str) = word.strip_prefix("0b").or_else(|| word.strip_prefix('%')) {
+        u64::from_str_radix(bin_str, 2).ok()
+    } else if let Some(char_str) = word.strip_prefix('\'').and_then(|w| w.strip_suffix('\'')) {
+        let mut chars = char_str.chars();
+
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c as u64),
+            _ => None,
+        }
+    } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+        word.parse::<u64>().ok()
+    } else {
+        None
+    }
+}
+
+// Number literal, optionally followed by an A, B or N type suffix
+pub fn parse_literal(word: &str) -> Option<u64> {
+    parse_number(word).or_else(|| {
+        word.strip_suffix(['A', 'B', 'N'])
+            .filter(|n_str| !is_hexadecimal(n_str))
+            .and_then(parse_number)
+    })
+}
+
+// Hexadecimal literals take no type suffix, A and B are hexadecimal digits
+pub fn check_type_suffix(word: &str) -> Result<(), String> {
+    if is_hexadecimal(word) && word.ends_with(['A', 'B', 'N']) && parse_number(word).is_none() {
+        return Err(format!(
+            "Hexadecimal literal '{word}' can't have a type suffix, leave it untyped"
+        ));
+    }
+
+    Ok(())
+}
+
+fn is_hexadecimal(word: &str) -> bool {
+    word.starts_with("0x") || word.starts_with(['$', '#'])
+}
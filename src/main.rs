@@ -2,47 +2,195 @@
 #![allow(clippy::too_many_lines)]
 #![allow(clippy::cast_lossless)]
 #![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_sign_loss)]
 
+mod expression;
 mod instruction_generator;
+mod lexer;
+mod octo;
 mod parser;
 mod tests;
 
+use std::path::Path;
+
+struct Arguments {
+    input_file: String,
+    output_file: String,
+    listing_file: Option<String>,
+    options: parser::Options,
+}
+
 fn main() {
-    if let Some(input_file) = std::env::args().nth(1) {
-        if let Some(output_file) = std::env::args().nth(2) {
-            match std::fs::read_to_string(input_file) {
-                Ok(input_str) => match parser::parse(&input_str) {
-                    Ok(binary_u16) => {
-                        let mut binary_u8 = Vec::<u8>::new();
-
-                        for n in binary_u16 {
-                            binary_u8.push((n >> 8) as u8);
-                            binary_u8.push((n & 0xFF) as u8);
+    match parse_arguments(std::env::args().skip(1)) {
+        Ok(arguments) => match std::fs::read_to_string(&arguments.input_file) {
+            Ok(input_str) => match parser::assemble(
+                &input_str,
+                Some(Path::new(&arguments.input_file)),
+                &arguments.options,
+            ) {
+                Ok(assembly) => match std::fs::write(arguments.output_file, assembly.binary) {
+                    Ok(()) => {
+                        for warning in assembly.warnings {
+                            println!("{warning}");
                         }
 
-                        match std::fs::write(output_file, binary_u8) {
-                            Ok(()) => {
-                                println!("Assembly successfull !");
-                            }
+                        if let Some(listing_file) = arguments.listing_file {
+                            let listing = assembly.listing.join("\n") + "\n";
 
-                            Err(write_error) => {
-                                println!("Error, can't write output file : {write_error}");
+                            if let Err(write_error) = std::fs::write(listing_file, listing) {
+                                println!("Error, can't write listing file : {write_error}");
+                                return;
                             }
                         }
+
+                        for (name, start, size) in assembly.sections {
+                            println!("Section {name} at 0x{start:03X}, {size} bytes");
+                        }
+
+                        if assembly.ram_size > 0 {
+                            println!(
+                                "Variables use {} bytes of RAM from 0x{:03X}",
+                                assembly.ram_size, assembly.ram_start
+                            );
+                        }
+
+                        println!("Assembly successfull !");
                     }
 
-                    Err(parse_error) => {
-                        println!("{parse_error}");
+                    Err(write_error) => {
+                        println!("Error, can't write output file : {write_error}");
                     }
                 },
-                Err(read_error) => {
-                    println!("Error can't read input file : {read_error}");
+
+                Err(parse_error) => {
+                    println!("{parse_error}");
                 }
+            },
+            Err(read_error) => {
+                println!("Error can't read input file : {read_error}");
             }
-        } else {
-            println!("Error need output file as second argument");
+        },
+
+        Err(argument_error) => {
+            println!("{argument_error}");
+        }
+    }
+}
+
+// Usage : ch8asm [--origin address] [--padding byte] [--dialect native|cowgod] [--strict]
+//                [--listing listing_file] [--sections section,...] [-I include_dir]...
+//                [-D NAME[=value]]... input_file output_file
+// An input file ending with .8o is read as Octo source
+fn parse_arguments(mut args: impl Iterator<Item = String>) -> Result<Arguments, String> {
+    let mut files = Vec::<String>::new();
+    let mut listing_file = None;
+    let mut options = parser::Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // 0x200 for most interpreters, 0x600 for ETI-660 programs
+            "--origin" => {
+                options.origin = parse_option_number(&arg, args.next().as_deref(), 0xFFF)? as u16;
+            }
+            "--padding" => {
+                options.padding = parse_option_number(&arg, args.next().as_deref(), 0xFF)? as u8;
+            }
+            "--strict" => options.strict = true,
+            "--listing" => match args.next() {
+                Some(file) => listing_file = Some(file),
+                None => return Err("Error --listing needs a file as value".to_owned()),
+            },
+            "--dialect" => match args.next().as_deref().and_then(parser::Dialect::from_name) {
+                Some(dialect) => options.dialect = dialect,
+                None => return Err("Error --dialect needs native or cowgod as value".to_owned()),
+            },
+            // Placement order of the sections, code, data and bss by default
+            "--sections" => match args.next().as_deref().map(parse_sections) {
+                Some(Some(sections)) => options.sections = sections,
+                _ => {
+                    return Err(
+                        "Error --sections needs section names separated by commas".to_owned()
+                    )
+                }
+            },
+            "-I" => match args.next() {
+                Some(dir) => options.include_dirs.push(dir.into()),
+                None => return Err("Error -I needs a directory as value".to_owned()),
+            },
+            _ if arg.starts_with("-I") => options.include_dirs.push(arg[2..].into()),
+            "-D" => match args.next() {
+                Some(define) => options.defines.push(parse_define(&define)?),
+                None => return Err("Error -D needs NAME or NAME=value as value".to_owned()),
+            },
+            _ if arg.starts_with("-D") => options.defines.push(parse_define(&arg[2..])?),
+            _ if arg.starts_with('-') => return Err(format!("Error unknown option {arg}")),
+            _ => files.push(arg),
         }
-    } else {
-        println!("Error needs input file as first argument");
     }
+
+    let mut files = files.into_iter();
+
+    let Some(input_file) = files.next() else {
+        return Err("Error needs input file as first argument".to_owned());
+    };
+
+    let Some(output_file) = files.next() else {
+        return Err("Error need output file as second argument".to_owned());
+    };
+
+    if files.next().is_some() {
+        return Err("Error too many arguments".to_owned());
+    }
+
+    Ok(Arguments {
+        input_file,
+        output_file,
+        listing_file,
+        options,
+    })
+}
+
+fn parse_option_number(option: &str, value: Option<&str>, max: u64) -> Result<u64, String> {
+    match value.and_then(lexer::parse_number) {
+        Some(n) if n <= max => Ok(n),
+        _ => Err(format!(
+            "Error {option} needs a number up to {max} as value"
+        )),
+    }
+}
+
+fn parse_sections(names: &str) -> Option<Vec<String>> {
+    let sections = names.split(',').map(str::to_owned).collect::<Vec<String>>();
+
+    sections
+        .iter()
+        .all(|name| lexer::is_symbol_name(name))
+        .then_some(sections)
+}
+
+// NAME=value, or NAME alone to define it as 1
+fn parse_define(define: &str) -> Result<(String, i64), String> {
+    let (name, value) = match define.split_once('=') {
+        Some((name, value_str)) => {
+            let (abs_str, sign) = match value_str.strip_prefix('-') {
+                Some(abs_str) => (abs_str, -1),
+                None => (value_str, 1),
+            };
+            let value = lexer::parse_number(abs_str)
+                .and_then(|n| i64::try_from(n).ok())
+                .map(|n| sign * n);
+
+            match value {
+                Some(value) => (name, value),
+                None => return Err(format!("Error invalid value '{value_str}' for -D {name}")),
+            }
+        }
+        None => (define, 1),
+    };
+
+    if !lexer::is_symbol_name(name) {
+        return Err(format!("Error invalid constant name '{name}' for -D"));
+    }
+
+    Ok((name.to_owned(), value))
 }
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::Path;
+use std::vec::IntoIter;
+
+use crate::lexer::{is_symbol_name, is_v_register_name, parse_number, Token};
+
+// Lines of native syntax, with the number of the Octo line each one comes from
+pub type Lines = Vec<(usize, Vec<Token>)>;
+
+enum Block {
+    Loop {
+        start: String,
+        end: Option<String>, // created by the first while
+    },
+    If {
+        else_label: String,
+        end_label: String,
+        has_else: bool,
+    },
+    Macro,
+}
+
+struct Translator<'a> {
+    words: Peekable<IntoIter<(usize, &'a str)>>,
+    lines: Lines,
+    line_n: usize,                   // line of the statement being translated
+    blocks: Vec<(usize, Block)>,     // open blocks and the line where they start
+    aliases: HashMap<String, Token>, // register named by each :alias
+    macros: HashMap<&'a str, usize>, // number of arguments of each macro
+    label_count: usize,              // labels generated for loops and ifs so far
+}
+
+// Left operand, comparison and right operand, which key and -key don't have
+type Condition<'a> = (Vec<Token>, &'a str, Option<Vec<Token>>);
+
+const ASSIGNMENTS: [(&str, &str); 9] = [
+    (":=", "LD"),
+    ("+=", "ADD"),
+    ("-=", "SUB"),
+    ("=-", "SUBN"),
+    ("|=", "OR"),
+    ("&=", "AND"),
+    ("^=", "XOR"),
+    (">>=", "SHR"),
+    ("<<=", "SHL"),
+];
+
+pub fn is_octo_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "8o")
+}
+
+// Octo statements can span several lines and share a line, so the source is read as a stream of
+// words, the error gives the line where translation stopped. A program starts at ': main', an
+// included file is a library that doesn't need one
+pub fn translate(text: &str, is_program: bool) -> Result<Lines, (usize, String)> {
+    let words = (1..)
+        .zip(text.lines())
+        .flat_map(|(line_n, line)| {
+            let code = line.split_once('#').map_or(line, |(code, _)| code);
+            code.split_whitespace().map(move |word| (line_n, word))
+        })
+        .collect::<Vec<(usize, &str)>>();
+
+    let mut translator = Translator {
+        words: words.into_iter().peekable(),
+        lines: Lines::new(),
+        line_n: 1,
+        blocks: Vec::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        label_count: 0,
+    };
+
+    while let Some((line_n, word)) = translator.words.next() {
+        translator.line_n = line_n;
+        translator
+            .statement(word)
+            .map_err(|error| (translator.line_n, error))?;
+    }
+
+    if let Some((line_n, block)) = translator.blocks.pop() {
+        let error = match block {
+            Block::Loop { .. } => "'loop' without 'again'",
+            Block::If { .. } => "'if' without 'end'",
+            Block::Macro => "':macro' without '}'",
+        };
+
+        return Err((line_n, error.to_owned()));
+    }
+
+    if is_program {
+        translator.jump_to_main()?;
+    }
+
+    Ok(translator.lines)
+}
+
+impl<'a> Translator<'a> {
+    fn statement(&mut self, word: &'a str) -> Result<(), String> {
+        match word {
+            "clear" => self.instruction("CLS", Vec::new()),
+            "return" | ";" => self.instruction("RET", Vec::new()),
+            "jump" => self.instruction_with_values("JMP", 1)?,
+            "jump0" => self.instruction_with_values("JMPO", 1)?,
+            ":call" => self.instruction_with_values("CALL", 1)?,
+            "bcd" => self.instruction_with_values("BCD", 1)?,
+            "save" => self.instruction_with_values("STN", 1)?,
+            "load" => self.instruction_with_values("LDN", 1)?,
+            "sprite" => self.instruction_with_values("DRW", 3)?,
+            ":byte" => self.instruction_with_values("DB", 1)?,
+            ":org" => self.instruction_with_values(".org", 1)?,
+
+            ":" => {
+                let name = self.next_word()?;
+                self.label(name);
+            }
+
+            ":const" => {
+                let name = self.next_word()?;
+                let mut line = vec![Token::Word(name.to_owned()), Token::Word("EQU".to_owned())];
+
+                line.extend(self.value()?);
+                self.emit(line);
+            }
+
+            ":alias" => {
+                let name = self.next_word()?;
+
+                match self.value()?.as_slice() {
+                    [register @ Token::Word(word)] if word.starts_with('V') => {
+                        self.aliases.insert(name.to_owned(), register.clone());
+                    }
+                    _ => return Err(format!(":alias {name} needs a register")),
+                }
+            }
+
+            ":include" => {
+                let word = self.next_word()?;
+
+                let Some(path) = word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) else {
+                    return Err(":include needs a file path between double quotes".to_owned());
+                };
+
+                self.emit(vec![
+                    Token::Word(".include".to_owned()),
+                    Token::Str(path.to_owned()),
+                ]);
+            }
+
+            ":macro" => self.macro_definition()?,
+
+            "}" => match self.blocks.pop() {
+                Some((_, Block::Macro)) => self.emit(vec![Token::Word(".endm".to_owned())]),
+                _ => return Err("'}' without ':macro'".to_owned()),
+            },
+
+            "loop" => {
+                let start = self.new_label("loop");
+
+                self.label(&start);
+                self.blocks
+                    .push((self.line_n, Block::Loop { start, end: None }));
+            }
+
+            "while" => {
+                let condition = self.condition()?;
+                let new_end = self.new_label("loop_end");
+
+                let Some(end) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find_map(|(_, block)| match block {
+                        Block::Loop { end, .. } => Some(end),
+                        _ => None,
+                    })
+                else {
+                    return Err("'while' outside of a loop".to_owned());
+                };
+
+                let end = end.get_or_insert(new_end).clone();
+
+                self.skip(condition, true);
+                self.instruction("JMP", vec![vec![Token::Word(end)]]);
+            }
+
+            "again" => {
+                let Some((_, Block::Loop { start, end })) = self.blocks.pop() else {
+                    return Err("'again' without 'loop'".to_owned());
+                };
+
+                self.instruction("JMP", vec![vec![Token::Word(start)]]);
+
+                if let Some(end) = end {
+                    self.label(&end);
+                }
+            }
+
+            "if" => {
+                let condition = self.condition()?;
+
+                match self.next_word()? {
+                    "then" => {
+                        self.skip(condition, false);
+
+                        let (line_n, word) = self.next_line_word()?;
+                        self.line_n = line_n;
+                        self.statement(word)?;
+                    }
+                    "begin" => {
+                        let else_label = self.new_label("else");
+                        let end_label = self.new_label("end");
+
+                        self.skip(condition, true);
+                        self.instruction("JMP", vec![vec![Token::Word(else_label.clone())]]);
+
+                        self.blocks.push((
+                            self.line_n,
+                            Block::If {
+                                else_label,
+                                end_label,
+                                has_else: false,
+                            },
+                        ));
+                    }
+                    word => return Err(format!("Expected 'then' or 'begin' instead of '{word}'")),
+                }
+            }
+
+            "else" => {
+                let Some((
+                    _,
+                    Block::If {
+                        else_label,
+                        end_label,
+                        has_else: has_else @ false,
+                    },
+                )) = self.blocks.last_mut()
+                else {
+                    return Err("'else' without 'if ... begin'".to_owned());
+                };
+
+                *has_else = true;
+                let (else_label, end_label) = (else_label.clone(), end_label.clone());
+
+                self.instruction("JMP", vec![vec![Token::Word(end_label)]]);
+                self.label(&else_label);
+            }
+
+            "end" => {
+                let Some((
+                    _,
+                    Block::If {
+                        else_label,
+                        end_label,
+                        has_else,
+                    },
+                )) = self.blocks.pop()
+                else {
+                    return Err("'end' without 'if ... begin'".to_owned());
+                };
+
+                self.label(if has_else { &end_label } else { &else_label });
+            }
+
+            "i" => {
+                let register = vec![Token::Word("I".to_owned())];
+
+                match self.next_word()? {
+                    ":=" if self.words.peek().is_some_and(|(_, word)| *word == "hex") => {
+                        self.words.next();
+                        self.instruction_with_values("SPR", 1)?;
+                    }
+                    ":=" => {
+                        let value = self.value()?;
+                        self.instruction("LD", vec![register, value]);
+                    }
+                    "+=" => {
+                        let value = self.value()?;
+                        self.instruction("ADD", vec![register, value]);
+                    }
+                    word => {
+                        return Err(format!("Expected ':=' or '+=' after i instead of '{word}'"));
+                    }
+                }
+            }
+
+            "delay" | "buzzer" => {
+                let timer = if word == "delay" { "DT" } else { "ST" };
+                self.expect(":=")?;
+
+                let value = self.value()?;
+                self.instruction("LD", vec![vec![Token::Word(timer.to_owned())], value]);
+            }
+
+            _ if word.starts_with(':') => return Err(format!("Unknown Octo directive '{word}'")),
+
+            _ if is_number(word) => {
+                let value = self.convert(word)?;
+                self.instruction("DB", vec![value]);
+            }
+
+            _ => {
+                let is_assignment = self.words.peek().is_some_and(|(_, next)| {
+                    ASSIGNMENTS.iter().any(|(operator, _)| operator == next)
+                });
+
+                if let Some(&argument_count) = self.macros.get(word) {
+                    self.instruction_with_values(word, argument_count)?;
+                } else if is_assignment {
+                    let register = self.convert(word)?;
+                    self.assignment(register)?;
+                } else {
+                    let address = self.convert(word)?;
+                    self.instruction("CALL", vec![address]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Jumps to main first unless main is the first code of the program, constants and macros
+    // don't emit code
+    fn jump_to_main(&mut self) -> Result<(), (usize, String)> {
+        let is_main = |line: &[Token]| matches!(line, [Token::Word(main), Token::Punct(":")] if main == "main");
+
+        if !self.lines.iter().any(|(_, line)| is_main(line)) {
+            return Err((1, "Octo program has no ': main' to start from".to_owned()));
+        }
+
+        let mut in_macro = false;
+
+        for (_, line) in &self.lines {
+            match line.as_slice() {
+                [Token::Word(word), ..] if word == ".macro" => in_macro = true,
+                [Token::Word(word)] if word == ".endm" => in_macro = false,
+                _ if in_macro => {}
+                [_, Token::Word(equ), ..] if equ == "EQU" => {}
+                line if is_main(line) => return Ok(()),
+                _ => break,
+            }
+        }
+
+        let jump = vec![
+            Token::Word("JMP".to_owned()),
+            Token::Word("main".to_owned()),
+        ];
+        self.lines.insert(0, (1, jump));
+
+        Ok(())
+    }
+
+    fn assignment(&mut self, register: Vec<Token>) -> Result<(), String> {
+        let operator = self.next_word()?;
+        let mnemonic = ASSIGNMENTS
+            .iter()
+            .find(|(assignment, _)| *assignment == operator)
+            .map_or("LD", |(_, mnemonic)| *mnemonic);
+
+        let source = match (operator, self.words.peek().map(|(_, word)| *word)) {
+            (":=", Some("key")) => {
+                self.words.next();
+                self.instruction("LDK", vec![register]);
+                return Ok(());
+            }
+            (":=", Some("random")) => {
+                self.words.next();
+                let mask = self.value()?;
+                self.instruction("RND", vec![register, mask]);
+                return Ok(());
+            }
+            (":=", Some("delay")) => {
+                self.words.next();
+                vec![Token::Word("DT".to_owned())]
+            }
+            // There is no opcode subtracting a constant, its negated byte is added instead
+            ("-=", _) => {
+                let value = self.value()?;
+
+                if let [Token::Word(source)] = value.as_slice() {
+                    if is_v_register_name(source) {
+                        self.instruction("SUB", vec![register, value]);
+                        return Ok(());
+                    }
+                }
+
+                let mut negated = vec![Token::Punct("-"), Token::Punct("(")];
+                negated.extend(value);
+                negated.extend([
+                    Token::Punct(")"),
+                    Token::Punct("&"),
+                    Token::Word("0xFF".to_owned()),
+                ]);
+
+                self.instruction("ADD", vec![register, negated]);
+                return Ok(());
+            }
+            _ => self.value()?,
+        };
+
+        self.instruction(mnemonic, vec![register, source]);
+        Ok(())
+    }
+
+    // :macro name argument... { body }
+    fn macro_definition(&mut self) -> Result<(), String> {
+        let name = self.next_word()?;
+        let mut line = vec![
+            Token::Word(".macro".to_owned()),
+            Token::Word(name.to_owned()),
+        ];
+        let mut argument_count = 0;
+
+        loop {
+            match self.next_word()? {
+                "{" => break,
+                argument => {
+                    if argument_count > 0 {
+                        line.push(Token::Punct(","));
+                    }
+                    line.push(Token::Word(argument.to_owned()));
+                    argument_count += 1;
+                }
+            }
+        }
+
+        self.macros.insert(name, argument_count);
+        self.blocks.push((self.line_n, Block::Macro));
+        self.emit(line);
+
+        Ok(())
+    }
+
+    // Reads vx == value, vx != value, vx key or vx -key
+    fn condition(&mut self) -> Result<Condition<'a>, String> {
+        let left = self.value()?;
+
+        match self.next_word()? {
+            operator @ ("key" | "-key") => Ok((left, operator, None)),
+            operator @ ("==" | "!=") => Ok((left, operator, Some(self.value()?))),
+            operator => Err(format!("Comparison '{operator}' isn't supported")),
+        }
+    }
+
+    // Emits the instruction that skips the next one when the condition is when
+    fn skip(&mut self, (left, operator, right): Condition, when: bool) {
+        let mnemonic = match (operator, when) {
+            ("==", true) | ("!=", false) => "SEQ",
+            ("==" | "!=", _) => "SNE",
+            ("key", true) | ("-key", false) => "SKP",
+            _ => "SKNP",
+        };
+
+        self.instruction(mnemonic, std::iter::once(left).chain(right).collect());
+    }
+
+    fn instruction_with_values(&mut self, mnemonic: &str, count: usize) -> Result<(), String> {
+        let mut operands = Vec::<Vec<Token>>::new();
+
+        for _ in 0..count {
+            operands.push(self.value()?);
+        }
+
+        self.instruction(mnemonic, operands);
+        Ok(())
+    }
+
+    // Operands are separated by commas so that negative values stay apart
+    fn instruction(&mut self, mnemonic: &str, operands: Vec<Vec<Token>>) {
+        let mut line = vec![Token::Word(mnemonic.to_owned())];
+
+        for (i, operand) in operands.into_iter().enumerate() {
+            if i > 0 {
+                line.push(Token::Punct(","));
+            }
+            line.extend(operand);
+        }
+
+        self.emit(line);
+    }
+
+    fn label(&mut self, name: &str) {
+        self.emit(vec![Token::Word(name.to_owned()), Token::Punct(":")]);
+    }
+
+    fn emit(&mut self, line: Vec<Token>) {
+        self.lines.push((self.line_n, line));
+    }
+
+    // Generated labels contain '@' so that they can't collide with the names of the program
+    fn new_label(&mut self, kind: &str) -> String {
+        self.label_count += 1;
+        format!("_{kind}@{}", self.label_count)
+    }
+
+    fn value(&mut self) -> Result<Vec<Token>, String> {
+        let word = self.next_word()?;
+        self.convert(word)
+    }
+
+    // Turns a register, number or name into native tokens
+    fn convert(&self, word: &str) -> Result<Vec<Token>, String> {
+        if let Some(register) = self.aliases.get(word) {
+            return Ok(vec![register.clone()]);
+        }
+
+        if let Some(n) = word
+            .strip_prefix(['v', 'V'])
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+        {
+            return Ok(vec![Token::Word(format!("V{n}"))]);
+        }
+
+        if is_number(word) {
+            return Ok(match word.strip_prefix('-') {
+                Some(digits) => vec![Token::Punct("-"), Token::Word(digits.to_owned())],
+                None => vec![Token::Word(word.to_owned())],
+            });
+        }
+
+        if is_symbol_name(word) {
+            Ok(vec![Token::Word(word.to_owned())])
+        } else {
+            Err(format!("Unexpected '{word}'"))
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next_word()? {
+            word if word == expected => Ok(()),
+            word => Err(format!("Expected '{expected}' instead of '{word}'")),
+        }
+    }
+
+    fn next_word(&mut self) -> Result<&'a str, String> {
+        self.next_line_word().map(|(_, word)| word)
+    }
+
+    fn next_line_word(&mut self) -> Result<(usize, &'a str), String> {
+        self.words
+            .next()
+            .ok_or_else(|| "Unexpected end of the program".to_owned())
+    }
+}
+
+fn is_number(word: &str) -> bool {
+    parse_number(word.strip_prefix('-').unwrap_or(word)).is_some()
+}
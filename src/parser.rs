@@ -1,60 +1,1913 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::expression::{is_binary_operator, parse_expression, Expression, Tokens};
 use crate::instruction_generator::{generate_instruction, Instruction, Parameter, Register};
+use crate::lexer::{
+    check_type_suffix, is_register_name, is_symbol_name, is_symbol_reference, is_v_register_name,
+    parse_number, tokenize, Token, SPACED_SIGNS,
+};
+use crate::octo;
+
+#[derive(Clone)]
+enum Operand {
+    Parameter(Parameter),   // register or number tagged with A, B or N
+    Expression(Expression), // evaluated to an untyped Number once every symbol is known
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Parameter(parameter) => write!(f, "{parameter}"),
+            Operand::Expression(expression) => write!(f, "{expression}"),
+        }
+    }
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(Expression, Scope), // evaluated in the scope where it is defined
+    Register(u8),                // .alias of a V register
+    Variable(u16, u16), // offset and size of a .var, a Label once the end of the program is known
+    SectionLabel(usize, u16), // section and offset of a label, a Label once the section is placed
+}
+
+// Prefix of the names defined in the current .scope blocks, like "outer.inner."
+type Scope = Rc<str>;
+
+type Symbols = HashMap<String, (Symbol, Location)>; // symbol and where it is defined
+
+#[derive(Clone)]
+struct Location {
+    file: Option<Rc<Path>>, // included file, None for the main source
+    line_n: usize,
+    expansion: Option<Rc<Expansion>>, // macro call or .rept iteration the line comes from
+}
+
+struct Expansion {
+    origin: String, // what the lines are expanded from, followed by the position of call
+    call: Location,
+}
+
+impl Location {
+    fn fmt_position(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "in {} line {}", file.display(), self.line_n),
+            // Line 0 of the main source is where the constants of the command line are defined
+            None if self.line_n == 0 => write!(f, "on the command line"),
+            None => write!(f, "line {}", self.line_n),
+        }
+    }
+}
+
+// Deeply nested expansions only show the two innermost macro calls and the outermost one
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_position(f)?;
+
+        let mut expansion = self.expansion.as_deref();
+        let mut depth = 0;
+
+        while let Some(Expansion { origin, call }) = expansion {
+            if depth < 2 || call.expansion.is_none() {
+                write!(f, " in {origin} ")?;
+                call.fmt_position(f)?;
+            } else if depth == 2 {
+                write!(f, " ...")?;
+            }
+
+            expansion = call.expansion.as_deref();
+            depth += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct SourceLine {
+    location: Location,
+    tokens: Result<Vec<Token>, String>, // a tokenizing error is reported when the line is assembled
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    locals: Vec<String>, // labels defined by the body, renamed in each expansion
+    body: Vec<SourceLine>,
+    location: Location,
+}
+
+// Block of lines opened by .if, .ifdef or .ifndef
+struct Conditional {
+    directive: String,
+    location: Location, // opening line, where an unclosed block is reported
+    active: bool,       // the lines of the current branch are assembled
+    done: bool,         // a branch was already taken, or the whole block is skipped
+    has_else: bool,
+    runtime: Option<usize>, // number of the generated labels of an .if on registers or keys
+}
+
+// Statements are grouped in sections, placed one after the other once every line is assembled
+struct Section {
+    name: String,
+    statements: Vec<(Location, Scope, Statement)>,
+    size: usize,
+    alignment: usize, // largest .align of the section, its start is aligned on it
+    start: Option<usize>, // only known before the placement for the first section, at the origin
+}
+
+impl Section {
+    fn new(name: &str, start: Option<usize>) -> Section {
+        Section {
+            name: name.to_owned(),
+            statements: Vec::new(),
+            size: 0,
+            alignment: 1,
+            start,
+        }
+    }
+}
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+enum Statement {
+    Instruction(Instruction, Vec<Operand>),
+    Bytes(Vec<Operand>), // DB / .byte
+    Words(Vec<Operand>), // DW / .word, stored big endian like opcodes
+    Fill(usize, u8),     // count and value of the bytes
+    Space(usize, u8),    // like Fill, but left out of the ROM when nothing follows
+    Raw(Vec<u8>),        // bytes copied by .incbin
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(_, _) => 2,
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => 2 * values.len(),
+            Statement::Fill(count, _) | Statement::Space(count, _) => *count,
+            Statement::Raw(bytes) => bytes.len(),
+        }
+    }
+}
+
+// Statement as written in listings, with the native mnemonics
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, operands) = match self {
+            Statement::Instruction(instruction, operands) => (instruction.to_string(), operands),
+            Statement::Bytes(values) => (".byte".to_owned(), values),
+            Statement::Words(values) => (".word".to_owned(), values),
+            Statement::Fill(count, value) => return write!(f, ".fill {count}, {value}"),
+            Statement::Space(count, _) => return write!(f, ".space {count}"),
+            Statement::Raw(bytes) => return write!(f, "{} bytes of data", bytes.len()),
+        };
+
+        write!(f, "{name}")?;
+
+        for (i, operand) in operands.iter().enumerate() {
+            write!(f, "{}{operand}", if i == 0 { " " } else { ", " })?;
+        }
+
+        Ok(())
+    }
+}
+
+// Mnemonics and operand syntax of the instructions
+#[derive(Clone, Copy)]
+pub enum Dialect {
+    Native, // SEQ, JMP, JMPO, LDK, SPR, BCD, STN, LDN
+    Cowgod, // SE, JP, JP V0 addr, LD Vx K, LD F Vx, LD B Vx, LD [I] Vx, LD Vx [I]
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name {
+            "native" => Some(Dialect::Native),
+            "cowgod" => Some(Dialect::Cowgod),
+            _ => None,
+        }
+    }
+}
+
+pub struct Options {
+    pub origin: u16,                 // address where the interpreter loads the program
+    pub padding: u8,                 // byte filling the gaps left by .org, .align and .space
+    pub include_dirs: Vec<PathBuf>, // searched by .include after the directory of the including file
+    pub defines: Vec<(String, i64)>, // constants defined on the command line
+    pub dialect: Dialect,           // until a .dialect directive
+    pub strict: bool,               // warn about inconsistent casing
+    pub sections: Vec<String>,      // placement order of the sections, code is the default one
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            origin: 0x200,
+            padding: 0,
+            include_dirs: Vec::new(),
+            defines: Vec::new(),
+            dialect: Dialect::Native,
+            strict: false,
+            sections: vec!["code".to_owned(), "data".to_owned(), "bss".to_owned()],
+        }
+    }
+}
+
+struct Assembler<'a> {
+    options: &'a Options,
+    symbols: Symbols,
+    sections: Vec<Section>, // in the placement order, then in the order of their first .section
+    section: usize,         // section of the next statement
+    location: Location,     // line being assembled, errors are reported there
+    scope: Scope,
+    global_label: Option<String>, // last label not starting with '.', owner of the local labels
+    source_dir: PathBuf,          // directory of the main source
+    includes: Vec<PathBuf>,       // canonical paths of the files being assembled, to detect cycles
+    macros: HashMap<String, Rc<Macro>>,
+    charmap: HashMap<char, Expression>, // bytes emitted by .text for each character
+    dialect: Dialect,
+    file_casing: HashMap<Option<Rc<Path>>, (bool, Location)>, // strict mode : uppercase or not
+    warnings: Vec<String>,
+    expansion_depth: usize, // macro expansions being assembled
+    expansion_count: usize, // macro expansions so far, used to rename their local labels
+    block_count: usize,     // .if and .while on registers or keys so far, to name their labels
+    ram_size: usize,        // bytes of the .var declared so far
+}
+
+pub struct Assembly {
+    pub binary: Vec<u8>,
+    pub warnings: Vec<String>, // formatted like errors, in the order of the source
+    pub listing: Vec<String>,  // address, bytes and source line of each statement
+    pub ram_start: usize,      // address of the variables, right after the program
+    pub ram_size: usize,       // bytes used by the variables
+    pub sections: Vec<(String, usize, usize)>, // name, start address and size of the sections
+}
+
+// path is the file the text comes from, if any, it is used to find included files
+pub fn assemble(text: &str, path: Option<&Path>, options: &Options) -> Result<Assembly, String> {
+    // Only the first section of the order starts at a known address, the origin
+    let mut sections = Vec::<Section>::new();
+
+    for name in options.sections.iter().map(String::as_str).chain(["code"]) {
+        if !sections.iter().any(|section| section.name == name) {
+            let start = sections.is_empty().then_some(options.origin as usize);
+            sections.push(Section::new(name, start));
+        }
+    }
+
+    let code_section = sections.iter().position(|section| section.name == "code");
+
+    let mut assembler = Assembler {
+        options,
+        symbols: Symbols::new(),
+        sections,
+        section: code_section.unwrap_or_default(),
+        location: Location {
+            file: None,
+            line_n: 0,
+            expansion: None,
+        },
+        scope: Scope::from(""),
+        global_label: None,
+        source_dir: path
+            .and_then(Path::parent)
+            .map_or_else(PathBuf::new, Path::to_path_buf),
+        includes: path
+            .and_then(|path| path.canonicalize().ok())
+            .into_iter()
+            .collect(),
+        macros: HashMap::new(),
+        charmap: HashMap::new(),
+        dialect: options.dialect,
+        file_casing: HashMap::new(),
+        warnings: Vec::new(),
+        expansion_depth: 0,
+        expansion_count: 0,
+        block_count: 0,
+        ram_size: 0,
+    };
+
+    for (name, value) in &options.defines {
+        let symbol = (
+            Symbol::Constant(Expression::Number(*value), Scope::from("")),
+            assembler.location.clone(),
+        );
+        assembler.symbols.insert(name.clone(), symbol);
+    }
+
+    // First pass : parse every line, record the address of each label and the value of each constant
+    if let Err(line_error) =
+        assembler.assemble_text(text, None, path.is_some_and(octo::is_octo_file))
+    {
+        return Err(format!("Error {} : {line_error}", assembler.location));
+    }
+
+    // Placement : each section follows the previous one, aligned on its largest .align
+    let mut starts = Vec::<usize>::new();
+    let mut end = options.origin as usize;
+
+    for section in &assembler.sections {
+        let start = section
+            .start
+            .unwrap_or_else(|| end.next_multiple_of(section.alignment));
+        end = start + section.size;
+
+        if let (true, Some((location, _, _))) = (end > 0x1000, section.statements.first()) {
+            return Err(format!(
+                "Error {location} : Section '{}' ends at 0x{end:03X}, past the end of the 4096 bytes of memory",
+                section.name
+            ));
+        }
+
+        starts.push(start);
+    }
+
+    // The labels of the sections get their address, the variables are allocated after the
+    // program, reserved space included
+    let ram_start = end;
+
+    for (name, (symbol, location)) in &mut assembler.symbols {
+        match *symbol {
+            Symbol::SectionLabel(section, offset) => {
+                *symbol = Symbol::Label((starts[section] + offset as usize) as u16);
+            }
+            Symbol::Variable(offset, size) => {
+                let address = ram_start + offset as usize;
+
+                if address + size as usize > 0x1000 {
+                    return Err(format!(
+                        "Error {location} : Variable '{name}' at 0x{address:03X} goes past the end of the 4096 bytes of memory"
+                    ));
+                }
+
+                *symbol = Symbol::Label(address as u16);
+            }
+            _ => {}
+        }
+    }
+
+    // Second pass : replace symbols by their value and generate the binary
+    let mut binary = Vec::<u8>::new();
+    let mut listing = Vec::<String>::new();
+
+    let mut rom_size = 0;
+
+    for (section, section_start) in assembler.sections.iter().zip(&starts) {
+        // Gap left by the alignment of the section
+        let gap = section_start - (options.origin as usize + binary.len());
+        binary.extend(std::iter::repeat_n(options.padding, gap));
+
+        for (location, scope, statement) in &section.statements {
+            let start = binary.len();
+
+            if let Err(line_error) =
+                generate_statement(statement, scope, &assembler.symbols, &mut binary)
+            {
+                return Err(format!("Error {location} : {line_error}"));
+            }
+
+            if start < binary.len() {
+                let address = options.origin as usize + start;
+                listing.push(listing_line(address, &binary[start..], statement, location));
+            }
+
+            if !matches!(statement, Statement::Space(_, _)) {
+                rom_size = binary.len();
+            }
+        }
+    }
+
+    // The space reserved at the end of the program is left out of the ROM
+    binary.truncate(rom_size);
+
+    Ok(Assembly {
+        binary,
+        warnings: assembler.warnings,
+        listing,
+        ram_start,
+        ram_size: assembler.ram_size,
+        sections: assembler
+            .sections
+            .iter()
+            .zip(starts)
+            .filter(|(section, _)| section.size > 0)
+            .map(|(section, start)| (section.name.clone(), start, section.size))
+            .collect(),
+    })
+}
+
+impl Assembler<'_> {
+    // Octo sources are translated to lines of native syntax first
+    fn assemble_text(
+        &mut self,
+        text: &str,
+        file: Option<&Rc<Path>>,
+        is_octo: bool,
+    ) -> Result<(), String> {
+        let location = |line_n| Location {
+            file: file.cloned(),
+            line_n,
+            expansion: None,
+        };
+
+        let lines = if is_octo {
+            self.dialect = Dialect::Native;
+
+            match octo::translate(text, file.is_none()) {
+                Ok(lines) => lines
+                    .into_iter()
+                    .map(|(line_n, tokens)| SourceLine {
+                        location: location(line_n),
+                        tokens: Ok(tokens),
+                    })
+                    .collect::<Vec<SourceLine>>(),
+                Err((line_n, error)) => {
+                    self.location = location(line_n);
+                    return Err(error);
+                }
+            }
+        } else {
+            (1..)
+                .zip(text.lines())
+                .map(|(line_n, line)| SourceLine {
+                    location: location(line_n),
+                    tokens: tokenize(line),
+                })
+                .collect::<Vec<SourceLine>>()
+        };
+
+        self.assemble_lines(&lines)
+    }
+
+    fn assemble_lines(&mut self, lines: &[SourceLine]) -> Result<(), String> {
+        let mut i = 0;
+        let mut conditionals = Vec::<Conditional>::new();
+
+        while i < lines.len() {
+            self.location = lines[i].location.clone();
+
+            let active = conditionals
+                .last()
+                .is_none_or(|conditional| conditional.active);
+
+            // Skipped lines are only looked at to track the nesting of conditional blocks
+            let tokens = match &lines[i].tokens {
+                Ok(tokens) => tokens,
+                Err(_) if !active => {
+                    i += 1;
+                    continue;
+                }
+                Err(error) => return Err(error.clone()),
+            };
+
+            match first_word(tokens) {
+                // An .if on registers or keys is tested when the program runs, all its lines are
+                // assembled
+                Some(directive @ (".if" | ".ifdef" | ".ifndef")) => {
+                    let arguments = match directive {
+                        ".if" => self.resolve_aliases(&tokens[1..]),
+                        _ => tokens[1..].to_vec(),
+                    };
+
+                    let runtime = if active
+                        && directive == ".if"
+                        && is_runtime_condition(&arguments)
+                    {
+                        self.block_count += 1;
+                        self.skip_unless(&arguments, &format!("_if_else@{}", self.block_count))?;
+                        Some(self.block_count)
+                    } else {
+                        None
+                    };
+
+                    let condition =
+                        active && (runtime.is_some() || self.condition(directive, &arguments)?);
+
+                    conditionals.push(Conditional {
+                        directive: directive.to_owned(),
+                        location: self.location.clone(),
+                        active: condition,
+                        done: condition || !active,
+                        has_else: false,
+                        runtime,
+                    });
+                }
+
+                Some(directive @ (".elif" | ".else")) => {
+                    let Some(conditional) = conditionals.last_mut() else {
+                        return Err(format!("{directive} without .if"));
+                    };
+
+                    if conditional.has_else {
+                        return Err(format!("{directive} after .else"));
+                    }
+
+                    if directive == ".else" {
+                        if tokens.len() > 1 {
+                            return Err(format!("Unexpected '{}' after .else", tokens[1]));
+                        }
+
+                        if let Some(number) = conditional.runtime {
+                            self.push_jump(&format!("_if_end@{number}"))?;
+                            self.define_symbol(&format!("_if_else@{number}"), self.here())?;
+                        } else {
+                            conditional.active = !conditional.done;
+                            conditional.done = true;
+                        }
+                        conditional.has_else = true;
+                    } else if conditional.runtime.is_some() {
+                        return Err(
+                            ".elif can't follow an .if on registers or keys, nest an .if in .else"
+                                .to_owned(),
+                        );
+                    } else {
+                        let condition = !conditional.done && self.condition(".if", &tokens[1..])?;
+
+                        conditional.active = condition;
+                        conditional.done |= condition;
+                    }
+                }
+
+                Some(".endif") => match conditionals.pop() {
+                    Some(Conditional {
+                        runtime: Some(number),
+                        has_else,
+                        ..
+                    }) => {
+                        let name = if has_else { "_if_end" } else { "_if_else" };
+                        self.define_symbol(&format!("{name}@{number}"), self.here())?;
+                    }
+                    Some(_) => {}
+                    None => return Err(".endif without .if".to_owned()),
+                },
+
+                // A macro defined in a skipped block is skipped as a whole
+                Some(".macro") if !active => i = find_block_end(lines, i, ".macro", ".endm")?,
+
+                _ if !active => {}
+
+                Some(".macro") => {
+                    let end = find_block_end(lines, i, ".macro", ".endm")?;
+                    self.define_macro(&tokens[1..], &lines[i + 1..end])?;
+                    i = end;
+                }
+
+                Some(".endm") => return Err(".endm without .macro".to_owned()),
+
+                Some(".rept") => {
+                    let end = find_block_end(lines, i, ".rept", ".endr")?;
+                    self.repeat(&tokens[1..], &lines[i + 1..end])?;
+                    i = end;
+                }
+
+                Some(".endr") => return Err(".endr without .rept".to_owned()),
+
+                Some(".scope") => {
+                    let end = find_block_end(lines, i, ".scope", ".endscope")?;
+                    self.assemble_scope(&tokens[1..], &lines[i + 1..end])?;
+                    i = end;
+                }
+
+                Some(".endscope") => return Err(".endscope without .scope".to_owned()),
+
+                Some(".sprite") => {
+                    let end = find_block_end(lines, i, ".sprite", ".endsprite")?;
+                    self.sprite(&tokens[1..], &lines[i + 1..end])?;
+                    i = end;
+                }
+
+                Some(".endsprite") => return Err(".endsprite without .sprite".to_owned()),
+
+                Some(".while") => {
+                    let end = find_block_end(lines, i, ".while", ".endwhile")?;
+                    self.loop_while(&tokens[1..], &lines[i + 1..end])?;
+                    i = end;
+                }
+
+                Some(".endwhile") => return Err(".endwhile without .while".to_owned()),
+
+                _ => self.assemble_line(tokens)?,
+            }
+
+            i += 1;
+        }
+
+        if let Some(conditional) = conditionals.pop() {
+            self.location = conditional.location;
+            return Err(format!("{} without .endif", conditional.directive));
+        }
+
+        Ok(())
+    }
+
+    fn condition(&self, directive: &str, arguments: &[Token]) -> Result<bool, String> {
+        if directive == ".if" {
+            return Ok(self.evaluate_now(&parse_single_expression(arguments)?)? != 0);
+        }
+
+        match arguments {
+            [Token::Word(name)] if is_symbol_reference(name) => {
+                let name = self.qualify(name)?;
+                let defined = find_symbol(&self.symbols, &self.scope, &name).is_some();
+
+                Ok(defined == (directive == ".ifdef"))
+            }
+            _ => Err(format!("{directive} needs a symbol name")),
+        }
+    }
+
+    // The body is assembled once, followed by a jump back to the test of the condition
+    fn loop_while(&mut self, condition: &[Token], body: &[SourceLine]) -> Result<(), String> {
+        self.block_count += 1;
+
+        let start_location = self.location.clone();
+        let start = format!("_while@{}", self.block_count);
+        let end = format!("_while_end@{}", self.block_count);
+
+        self.define_symbol(&start, self.here())?;
+        self.skip_unless(&self.resolve_aliases(condition), &end)?;
+        self.assemble_lines(body)?;
+
+        self.location = start_location;
+        self.push_jump(&start)?;
+        self.define_symbol(&end, self.here())
+    }
+
+    // Jumps to exit_label unless the condition on registers or keys holds
+    fn skip_unless(&mut self, condition: &[Token], exit_label: &str) -> Result<(), String> {
+        let (instruction, operands) = runtime_condition(condition)?;
+
+        self.push_statement(Statement::Instruction(instruction, operands))?;
+        self.push_jump(exit_label)
+    }
+
+    fn push_jump(&mut self, label: &str) -> Result<(), String> {
+        let target = Operand::Expression(Expression::Symbol(label.to_owned()));
+        self.push_statement(Statement::Instruction(Instruction::Jmp, vec![target]))
+    }
+
+    // Symbols declared in the block get the name of the scope as prefix
+    fn assemble_scope(&mut self, arguments: &[Token], body: &[SourceLine]) -> Result<(), String> {
+        let name = match arguments {
+            [Token::Word(name)] if is_symbol_name(name) => name,
+            _ => return Err(".scope needs a name".to_owned()),
+        };
+
+        let outer_scope = Rc::clone(&self.scope);
+        let outer_global_label = self.global_label.take();
+
+        self.scope = Scope::from(format!("{outer_scope}{name}."));
+        self.assemble_lines(body)?;
+
+        self.scope = outer_scope;
+        self.global_label = outer_global_label;
+
+        Ok(())
+    }
+
+    // Each row is a string of # and . (or 1 and 0), up to 8 pixels wide. .sprite name, 16 is a
+    // 16x16 sprite of SUPER-CHIP, 2 bytes per row, whose name.height is 0 as DRW Vx Vy 0 draws it
+    fn sprite(&mut self, arguments: &[Token], body: &[SourceLine]) -> Result<(), String> {
+        let (name, width) = match arguments {
+            [Token::Word(name)] if is_symbol_name(name) => (name, 8),
+            [Token::Word(name), Token::Punct(","), Token::Word(width)]
+                if is_symbol_name(name) && width == "16" =>
+            {
+                (name, 16)
+            }
+            _ => return Err(".sprite needs a name, and 16 for a 16x16 sprite".to_owned()),
+        };
+
+        let start_location = self.location.clone();
+        let mut rows = Vec::<u16>::new();
+
+        for line in body {
+            self.location = line.location.clone();
+
+            let row_str = match line.tokens.as_deref()? {
+                [] => continue,
+                [Token::Str(row_str)] => row_str,
+                [token, ..] => return Err(format!("Sprite row must be a string, not '{token}'")),
+            };
+
+            if row_str.chars().count() > width {
+                return Err(format!(
+                    "Sprite row \"{row_str}\" is wider than {width} pixels"
+                ));
+            }
+
+            let mut row = 0u16;
+
+            for (column, c) in row_str.chars().enumerate() {
+                match c {
+                    '#' | '1' => row |= 0x8000 >> column,
+                    '.' | '0' => {}
+                    _ => return Err(format!("Sprite pixel '{c}' must be # or . (1 or 0)")),
+                }
+            }
+
+            rows.push(row);
+        }
+
+        self.location = start_location;
+
+        if rows.is_empty() {
+            return Err(format!("Sprite '{name}' has no rows"));
+        }
+        if width == 16 && rows.len() != 16 {
+            return Err(format!(
+                "Sprite '{name}' has {} rows, a 16x16 sprite needs 16",
+                rows.len()
+            ));
+        }
+
+        let height = if width == 16 { 0 } else { rows.len() as u16 };
+
+        let bytes = rows
+            .iter()
+            .flat_map(|row| row.to_be_bytes().into_iter().take(width / 8))
+            .collect();
+
+        // The height can't overflow once the rows fit in memory
+        let label = self.here();
+        self.push_statement(Statement::Raw(bytes))?;
+
+        self.define_symbol(name, label)?;
+        self.define_symbol(
+            &format!("{name}.height"),
+            Symbol::Constant(Expression::Number(height.into()), Rc::clone(&self.scope)),
+        )
+    }
+
+    fn assemble_line(&mut self, tokens: &[Token]) -> Result<(), String> {
+        // Labels of macro and .rept expansions don't own the local labels that follow
+        if let [Token::Word(name), Token::Punct(":"), ..] = tokens {
+            if is_symbol_name(name) && !name.contains('@') {
+                self.global_label = Some(name.clone());
+            }
+        }
+
+        // Local labels are named after their global label, the directive or mnemonic is kept
+        let mut qualified = Vec::<Token>::new();
+
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Word(word) if word.starts_with('.') && is_symbol_reference(word) => {
+                    let is_label = tokens.get(i + 1) == Some(&Token::Punct(":"));
+                    let is_directive =
+                        !is_label && (i == 0 || i == 2 && tokens[1] == Token::Punct(":"));
+
+                    if is_directive {
+                        qualified.push(token.clone());
+                    } else {
+                        qualified.push(Token::Word(self.qualify(word)?));
+                    }
+                }
+                _ => qualified.push(token.clone()),
+            }
+        }
+
+        let mut rest = &qualified[..];
+        let mut label = None;
+
+        if let [Token::Word(name), Token::Punct(":"), after_label @ ..] = rest {
+            let written_name = first_word(tokens).unwrap_or_default();
+
+            if !is_symbol_name(written_name.strip_prefix('.').unwrap_or(written_name)) {
+                return Err(format!("Invalid label name '{written_name}'"));
+            }
+            if self.address() > 0xFFF {
+                return Err(format!(
+                    "Label '{name}' is outside of the 4096 bytes of memory"
+                ));
+            }
+
+            self.define_symbol(name, self.here())?;
+            label = Some(name);
+            rest = after_label;
+        }
+
+        // NAME EQU value or .define NAME value
+        match rest {
+            [Token::Word(name), Token::Word(equ), value @ ..]
+                if equ.eq_ignore_ascii_case("EQU") =>
+            {
+                return self.define_constant(name, value);
+            }
+            [Token::Word(define), Token::Word(name), value @ ..] if define == ".define" => {
+                return self.define_constant(name, value);
+            }
+            _ => {}
+        }
+
+        let Some((first_token, arguments)) = rest.split_first() else {
+            return Ok(());
+        };
+
+        let Token::Word(instruction_str) = first_token else {
+            return Err(format!("Unexpected '{first_token}'"));
+        };
+
+        if let Some(definition) = self.macros.get(instruction_str) {
+            return self.expand_macro(instruction_str, &Rc::clone(definition), arguments);
+        }
+
+        let directive = match instruction_str.to_ascii_uppercase().as_str() {
+            "DB" => ".byte",
+            "DW" => ".word",
+            _ => instruction_str,
+        };
+
+        match directive {
+            ".define" => Err("Constant definition needs a name and a value".to_owned()),
+
+            ".dialect" => match arguments {
+                [Token::Word(name)] => match Dialect::from_name(name) {
+                    Some(dialect) => {
+                        self.dialect = dialect;
+                        Ok(())
+                    }
+                    None => Err(format!("Unknown dialect '{name}'")),
+                },
+                _ => Err(".dialect needs a dialect name".to_owned()),
+            },
+
+            ".alias" | ".reg" => self.define_alias(directive, arguments),
+
+            // The section lasts until the next .section, included files included
+            ".section" => match arguments {
+                [Token::Word(name)] if is_symbol_name(name) => {
+                    let section = self.sections.iter().position(|s| s.name == *name);
+
+                    self.section = section.unwrap_or_else(|| {
+                        self.sections.push(Section::new(name, None));
+                        self.sections.len() - 1
+                    });
+                    Ok(())
+                }
+                _ => Err(".section needs a name".to_owned()),
+            },
+
+            // .var name size reserves size bytes of RAM after the program, nothing is emitted
+            ".var" => {
+                let [Token::Word(name), size @ ..] = arguments else {
+                    return Err(".var needs a name and a size".to_owned());
+                };
+
+                if !is_symbol_name(name) {
+                    return Err(format!("Invalid variable name '{name}'"));
+                }
+
+                let size = size.strip_prefix(&[Token::Punct(",")][..]).unwrap_or(size);
+                let size = self.evaluate_now(&parse_single_expression(size)?)?;
+
+                if !(1..=0x1000).contains(&size) {
+                    return Err(format!(".var size {size} isn't between 1 and 4096 bytes"));
+                }
+
+                if self.program_end() + self.ram_size + size as usize > 0x1000 {
+                    return Err(format!(
+                        "Variable '{name}' goes past the end of the 4096 bytes of memory"
+                    ));
+                }
+
+                let offset = self.ram_size as u16;
+                self.ram_size += size as usize;
+
+                self.define_symbol(name, Symbol::Variable(offset, size as u16))
+            }
+
+            ".include" => match arguments {
+                [Token::Str(path)] => self.include(path),
+                _ => Err(".include needs a file path between double quotes".to_owned()),
+            },
+
+            ".org" => {
+                let address = self.evaluate_now(&parse_single_expression(arguments)?)?;
+
+                if !(0..=0x1000).contains(&address) {
+                    return Err(format!(
+                        ".org address {address} is outside of the 4096 bytes of memory"
+                    ));
+                }
+
+                let address = address as usize;
+
+                if self.sections[self.section].start.is_none() {
+                    return Err(format!(
+                        ".org can't be used in section '{}', only in the section placed first",
+                        self.sections[self.section].name
+                    ));
+                }
+
+                if address < self.address() {
+                    return Err(format!(
+                        ".org can't move back from 0x{:03X} to 0x{address:03X} over emitted code",
+                        self.address()
+                    ));
+                }
+
+                let gap = address - self.address();
+                self.push_statement(Statement::Fill(gap, self.options.padding))
+            }
+
+            ".align" => {
+                let alignment = self.evaluate_now(&parse_single_expression(arguments)?)?;
+
+                if !(1..=0x1000).contains(&alignment) {
+                    return Err(format!(".align {alignment} isn't between 1 and 4096 bytes"));
+                }
+
+                let section = &mut self.sections[self.section];
+                section.alignment = section.alignment.max(alignment as usize);
+
+                let gap = self.address().next_multiple_of(alignment as usize) - self.address();
+                self.push_statement(Statement::Fill(gap, self.options.padding))
+            }
+
+            // .fill count[, value], the bytes are 0 when there is no value
+            ".fill" => {
+                let values = parse_expression_list(arguments)?;
+
+                let (count, value) = match &values[..] {
+                    [count] => (self.evaluate_now(count)?, 0),
+                    [count, value] => (self.evaluate_now(count)?, self.evaluate_now(value)?),
+                    _ => return Err(".fill needs a count and optionally a value".to_owned()),
+                };
+
+                if !(-0x80..=0xFF).contains(&value) {
+                    return Err(format!("Value {value} doesn't fit in a byte"));
+                }
+
+                let count = reserved_count(".fill", count)?;
+                self.push_statement(Statement::Fill(count, value as u8))
+            }
+
+            ".space" => {
+                let count = self.evaluate_now(&parse_single_expression(arguments)?)?;
+                let count = reserved_count(".space", count)?;
+
+                self.push_statement(Statement::Space(count, self.options.padding))
+            }
+
+            // .charmap "ABC", value maps A to value, B to value + 1 and C to value + 2
+            ".charmap" => {
+                let [Token::Str(characters), Token::Punct(","), value @ ..] = arguments else {
+                    return Err(
+                        ".charmap needs characters between double quotes and a value".to_owned(),
+                    );
+                };
+
+                let value = parse_single_expression(value)?;
+
+                for (offset, c) in (0..).zip(characters.chars()) {
+                    let mapped = match offset {
+                        0 => value.clone(),
+                        _ => Expression::Binary(
+                            "+",
+                            Box::new(value.clone()),
+                            Box::new(Expression::Number(offset)),
+                        ),
+                    };
+
+                    self.charmap.insert(c, mapped);
+                }
+
+                Ok(())
+            }
+
+            // The label of the line, if any, gets the length of the text as label.length
+            ".text" => {
+                let [Token::Str(text)] = arguments else {
+                    return Err(".text needs a text between double quotes".to_owned());
+                };
+
+                if text.is_empty() {
+                    return Err(".text needs at least one character".to_owned());
+                }
+
+                let mut values = Vec::<Operand>::new();
+
+                for c in text.chars() {
+                    match self.charmap.get(&c) {
+                        Some(value) => values.push(Operand::Expression(value.clone())),
+                        None => return Err(format!("Character '{c}' has no .charmap value")),
+                    }
+                }
+
+                let length = values.len();
+                self.push_statement(Statement::Bytes(values))?;
+
+                match label {
+                    Some(label) => self.define_symbol(
+                        &format!("{label}.length"),
+                        Symbol::Constant(
+                            Expression::Number((length as u16).into()),
+                            Rc::clone(&self.scope),
+                        ),
+                    ),
+                    None => Ok(()),
+                }
+            }
+
+            ".incbin" => {
+                let (path, range) = match arguments {
+                    [Token::Str(path)] => (path, &[][..]),
+                    [Token::Str(path), Token::Punct(","), range @ ..] => (path, range),
+                    _ => return Err(".incbin needs a file path between double quotes".to_owned()),
+                };
+
+                let mut bounds = Vec::<i64>::new();
+
+                for expression in parse_expression_list(range)? {
+                    bounds.push(self.evaluate_now(&expression)?);
+                }
+
+                self.incbin(path, &bounds)
+            }
+
+            // Any opcode, including those of extensions the assembler doesn't know
+            ".opcode" => {
+                let opcode = parse_single_expression(arguments)?;
+                self.push_statement(Statement::Words(vec![Operand::Expression(opcode)]))
+            }
+
+            ".byte" | ".word" => {
+                // Data has no registers, a value named i or dt is a symbol
+                self.check_casing(instruction_str, &[]);
+                let values = parse_operands(&mut arguments.iter().peekable())?;
+
+                if values.is_empty() {
+                    return Err(format!("{instruction_str} needs at least one value"));
+                }
+
+                if directive == ".byte" {
+                    self.push_statement(Statement::Bytes(values))
+                } else {
+                    self.push_statement(Statement::Words(values))
+                }
+            }
+
+            _ => {
+                self.check_casing(instruction_str, arguments);
+                let arguments = &self.resolve_aliases(arguments);
+
+                if let Some(expansion) = expand_pseudo_instruction(instruction_str, arguments)? {
+                    // Only the first opcode of the expansion would be skipped
+                    if let Some((_, _, Statement::Instruction(skip, _))) =
+                        self.sections[self.section].statements.last()
+                    {
+                        if matches!(
+                            skip,
+                            Instruction::Seq
+                                | Instruction::Sne
+                                | Instruction::Skp
+                                | Instruction::Sknp
+                        ) {
+                            self.warn(&format!(
+                                "{instruction_str} expands to several opcodes, {skip} only skips the first one"
+                            ));
+                        }
+                    }
+
+                    for (instruction, operands) in expansion {
+                        self.push_statement(Statement::Instruction(instruction, operands))?;
+                    }
+
+                    return Ok(());
+                }
+
+                let (instruction, operands) = match self.dialect {
+                    Dialect::Native => (
+                        parse_instruction(instruction_str)?,
+                        parse_instruction_operands(arguments)?,
+                    ),
+                    Dialect::Cowgod => parse_cowgod_instruction(instruction_str, arguments)?,
+                };
+
+                if matches!(instruction, Instruction::Sys) {
+                    self.warn(
+                        "SYS runs machine code of the COSMAC VIP, most interpreters ignore it",
+                    );
+                }
+
+                self.push_statement(Statement::Instruction(instruction, operands))
+            }
+        }
+    }
+
+    // In strict mode, the mnemonics and registers of a file must all be in uppercase or all in
+    // lowercase
+    fn check_casing(&mut self, mnemonic: &str, arguments: &[Token]) {
+        if !self.options.strict {
+            return;
+        }
+
+        let registers = arguments.iter().filter_map(|token| match token {
+            Token::Word(word) if is_register_name(word) => Some(word.as_str()),
+            _ => None,
+        });
+
+        let mnemonic = Some(mnemonic).filter(|word| !word.starts_with('.'));
+
+        for word in mnemonic.into_iter().chain(registers) {
+            let uppercase = word.to_ascii_uppercase() == word;
+
+            if !uppercase && word.to_ascii_lowercase() != word {
+                self.warn(&format!("'{word}' mixes uppercase and lowercase"));
+                continue;
+            }
+
+            let file = self.location.file.clone();
+
+            match self.file_casing.get(&file) {
+                Some((file_uppercase, since)) if *file_uppercase != uppercase => {
+                    let (case, file_case) = if uppercase {
+                        ("uppercase", "lowercase")
+                    } else {
+                        ("lowercase", "uppercase")
+                    };
+
+                    self.warn(&format!(
+                        "'{word}' is in {case} but this file is in {file_case} since {since}"
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    self.file_casing
+                        .insert(file, (uppercase, self.location.clone()));
+                }
+            }
+        }
+    }
+
+    fn warn(&mut self, message: &str) {
+        self.warnings
+            .push(format!("Warning {} : {message}", self.location));
+    }
+
+    fn define_constant(&mut self, name: &str, value: &[Token]) -> Result<(), String> {
+        if !is_symbol_name(name) {
+            return Err(format!("Invalid constant name '{name}'"));
+        }
+
+        let expression = parse_single_expression(value)?;
+        self.define_symbol(name, Symbol::Constant(expression, Rc::clone(&self.scope)))
+    }
+
+    // .alias name Vx, or .alias name other_alias, the alias is scoped like the labels
+    fn define_alias(&mut self, directive: &str, arguments: &[Token]) -> Result<(), String> {
+        let ([Token::Word(name), Token::Punct(","), register] | [Token::Word(name), register]) =
+            arguments
+        else {
+            return Err(format!("{directive} needs a name and a V register"));
+        };
+
+        if !is_symbol_name(name) || is_register_name(name) {
+            return Err(format!("Invalid alias name '{name}'"));
+        }
+
+        let x = match &self.resolve_aliases(std::slice::from_ref(register))[..] {
+            [Token::Word(register)] => match parse_parameter(register) {
+                Ok(Parameter::Register(Register::V(x))) => x,
+                _ => return Err(format!("{directive} needs a V register, not '{register}'")),
+            },
+            _ => return Err(format!("{directive} needs a V register, not '{register}'")),
+        };
+
+        // Other aliases of the register in the same scope, the aliases of the scopes around it are
+        // shadowed rather than live
+        let mut same_register = self
+            .symbols
+            .iter()
+            .filter_map(|(other, (symbol, location))| match symbol {
+                Symbol::Register(y) if *y == x => other
+                    .strip_prefix(&*self.scope)
+                    .filter(|other| !other.contains('.'))
+                    .map(|other| (other, location)),
+                _ => None,
+            })
+            .collect::<Vec<(&str, &Location)>>();
+
+        same_register.sort_by_key(|(other, _)| *other);
+
+        if let Some((other, location)) = same_register.first() {
+            let message = format!("'{name}' names V{x:X} like '{other}' defined {location}");
+            self.warn(&message);
+        }
+
+        self.define_symbol(name, Symbol::Register(x))
+    }
+
+    // Replaces the names of register aliases by the name of their register
+    fn resolve_aliases(&self, tokens: &[Token]) -> Vec<Token> {
+        tokens
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => match find_symbol(&self.symbols, &self.scope, word) {
+                    Some((_, (Symbol::Register(x), _))) => Token::Word(format!("V{x}")),
+                    _ => token.clone(),
+                },
+                _ => token.clone(),
+            })
+            .collect()
+    }
+
+    fn define_symbol(&mut self, name: &str, symbol: Symbol) -> Result<(), String> {
+        let name = format!("{}{name}", self.scope);
+
+        if let Some((_, defined_location)) = self.symbols.get(&name) {
+            let kind = match symbol {
+                Symbol::Label(_) | Symbol::SectionLabel(_, _) => "Label",
+                Symbol::Constant(_, _) => "Constant",
+                Symbol::Register(_) => "Alias",
+                Symbol::Variable(_, _) => "Variable",
+            };
+
+            return Err(format!(
+                "{kind} '{name}' is already defined {defined_location}"
+            ));
+        }
+
+        self.symbols.insert(name, (symbol, self.location.clone()));
+
+        Ok(())
+    }
+
+    // Gives the name of a local label (.loop) inside its global label (main.loop)
+    fn qualify(&self, name: &str) -> Result<String, String> {
+        if !name.starts_with('.') {
+            return Ok(name.to_owned());
+        }
+
+        match &self.global_label {
+            Some(global_label) => Ok(format!("{global_label}{name}")),
+            None => Err(format!(
+                "Local label '{name}' has no global label before it"
+            )),
+        }
+    }
+
+    fn push_statement(&mut self, statement: Statement) -> Result<(), String> {
+        let size = statement.size();
+
+        if self.program_end() + size > 0x1000 {
+            return Err(format!(
+                "{size} bytes at 0x{:03X} go past the end of the 4096 bytes of memory",
+                self.address()
+            ));
+        }
+
+        let section = &mut self.sections[self.section];
+
+        // The bss section holds what the program writes at run time, it can't have initial values
+        if section.name == "bss" && size > 0 && !matches!(statement, Statement::Space(_, _)) {
+            return Err("Section 'bss' can only reserve memory with .space".to_owned());
+        }
+
+        section.size += size;
+        section
+            .statements
+            .push((self.location.clone(), Rc::clone(&self.scope), statement));
+
+        Ok(())
+    }
+
+    // Address of the next statement, counted from the start of its section when the section is
+    // placed after the first one
+    fn address(&self) -> usize {
+        let section = &self.sections[self.section];
+        section.start.unwrap_or_default() + section.size
+    }
+
+    // Label of the next statement
+    fn here(&self) -> Symbol {
+        let section = &self.sections[self.section];
+
+        match section.start {
+            Some(start) => Symbol::Label((start + section.size) as u16),
+            None => Symbol::SectionLabel(self.section, section.size as u16),
+        }
+    }
+
+    // End of the program when the sections follow each other without alignment gaps
+    fn program_end(&self) -> usize {
+        let size = self
+            .sections
+            .iter()
+            .map(|section| section.size)
+            .sum::<usize>();
+        self.options.origin as usize + size
+    }
+
+    // bounds holds the optional offset and length of the data to copy from the file
+    fn incbin(&mut self, path: &str, bounds: &[i64]) -> Result<(), String> {
+        let file_path = self.find_include(path)?;
+
+        let data = match std::fs::read(&file_path) {
+            Ok(data) => data,
+            Err(error) => return Err(format!("Can't read '{}' : {error}", file_path.display())),
+        };
+
+        let mut bounds = bounds.iter().map(|&bound| usize::try_from(bound));
+
+        let offset = match bounds.next() {
+            Some(Ok(offset)) => offset,
+            Some(Err(_)) => return Err(".incbin offset can't be negative".to_owned()),
+            None => 0,
+        };
+
+        let length = match bounds.next() {
+            Some(Ok(length)) => length,
+            Some(Err(_)) => return Err(".incbin length can't be negative".to_owned()),
+            None => data.len().saturating_sub(offset),
+        };
+
+        if bounds.next().is_some() {
+            return Err(".incbin takes at most an offset and a length".to_owned());
+        }
+
+        let end = offset.saturating_add(length);
+
+        match data.get(offset..end) {
+            Some(bytes) => self.push_statement(Statement::Raw(bytes.to_vec())),
+            None => Err(format!(
+                "'{}' is only {} bytes long",
+                file_path.display(),
+                data.len()
+            )),
+        }
+    }
+
+    fn include(&mut self, path: &str) -> Result<(), String> {
+        let file_path = self.find_include(path)?;
 
-pub fn parse(text: &str) -> Result<Vec<u16>, String> {
-    let mut binary = Vec::<u16>::new();
+        let canonical_path = match file_path.canonicalize() {
+            Ok(canonical_path) => canonical_path,
+            Err(error) => return Err(format!("Can't open '{}' : {error}", file_path.display())),
+        };
 
-    let mut line_n = 1;
+        if self.includes.contains(&canonical_path) {
+            return Err(format!(
+                "Include cycle, '{}' is already being assembled",
+                file_path.display()
+            ));
+        }
+
+        let text = match std::fs::read_to_string(&file_path) {
+            Ok(text) => text,
+            Err(error) => return Err(format!("Can't read '{}' : {error}", file_path.display())),
+        };
+
+        let include_location = self.location.clone();
+        let include_dialect = self.dialect;
+
+        self.includes.push(canonical_path);
+        let is_octo = octo::is_octo_file(&file_path);
+        self.assemble_text(&text, Some(&Rc::from(file_path)), is_octo)?;
+        self.includes.pop();
+
+        // A .dialect directive lasts until the end of its file
+        self.location = include_location;
+        self.dialect = include_dialect;
+
+        Ok(())
+    }
+
+    // .macro name parameter... whose body lines are given without the .macro and .endm lines
+    fn define_macro(&mut self, arguments: &[Token], body: &[SourceLine]) -> Result<(), String> {
+        let Some((Token::Word(name), parameter_tokens)) = arguments.split_first() else {
+            return Err(".macro needs a name".to_owned());
+        };
 
-    for line in text.lines() {
-        match parse_line(line) {
-            Ok(line_binary) => {
-                if let Some(bin) = line_binary {
-                    binary.push(bin);
+        if !is_symbol_name(name)
+            || parse_instruction(name).is_ok()
+            || cowgod_mnemonic(name).is_ok()
+            || PSEUDO_INSTRUCTIONS
+                .iter()
+                .any(|(mnemonic, _)| mnemonic.eq_ignore_ascii_case(name))
+        {
+            return Err(format!("Invalid macro name '{name}'"));
+        }
+
+        if let Some(definition) = self.macros.get(name) {
+            return Err(format!(
+                "Macro '{name}' is already defined {}",
+                definition.location
+            ));
+        }
+
+        let mut parameters = Vec::<String>::new();
+
+        for token in parameter_tokens {
+            match token {
+                Token::Punct(",") => {}
+                Token::Word(parameter) if is_symbol_name(parameter) => {
+                    parameters.push(parameter.clone());
                 }
+                _ => return Err(format!("Invalid macro parameter '{token}'")),
             }
-            Err(line_error) => return Err(format!("Error line {line_n} : {line_error}")),
         }
 
-        line_n += 1;
+        let locals = local_labels(body, &parameters);
+
+        self.macros.insert(
+            name.clone(),
+            Rc::new(Macro {
+                parameters,
+                locals,
+                body: body.to_vec(),
+                location: self.location.clone(),
+            }),
+        );
+
+        Ok(())
     }
 
-    Ok(binary)
-}
+    fn expand_macro(
+        &mut self,
+        name: &str,
+        definition: &Macro,
+        arguments: &[Token],
+    ) -> Result<(), String> {
+        if self.expansion_depth == MAX_EXPANSION_DEPTH {
+            return Err(format!(
+                "Macro '{name}' is nested more than {MAX_EXPANSION_DEPTH} expansions deep"
+            ));
+        }
 
-fn parse_line(line: &str) -> Result<Option<u16>, String> {
-    let mut words = line.split(';').next().unwrap().split_whitespace();
+        let arguments = split_arguments(arguments)?;
 
-    let mut parameters = Vec::<Parameter>::new();
+        if arguments.len() != definition.parameters.len() {
+            return Err(format!(
+                "Macro '{name}' takes {} arguments but {} were given",
+                definition.parameters.len(),
+                arguments.len()
+            ));
+        }
 
-    if let Some(instruction_str) = words.next() {
-        match parse_instruction(instruction_str) {
-            Ok(instruction) => {
-                for parameter_str in words {
-                    match parse_parameter(parameter_str) {
-                        Ok(parameter) => {
-                            parameters.push(parameter);
-                        }
+        self.expansion_count += 1;
 
-                        Err(error) => return Err(error),
-                    }
+        // Local labels get a suffix that can't be written in the source, so they never collide
+        let local_suffix = format!("@{}", self.expansion_count);
+
+        self.expand(format!("macro '{name}' called"), &definition.body, |word| {
+            if let Some(i) = definition.parameters.iter().position(|p| p == word) {
+                arguments[i].to_vec()
+            } else if definition.locals.iter().any(|local| local == word) {
+                vec![Token::Word(format!("{word}{local_suffix}"))]
+            } else {
+                vec![Token::Word(word.to_owned())]
+            }
+        })
+    }
+
+    // .rept count [, var] assembles the body count times, var being the iteration number from 0
+    fn repeat(&mut self, arguments: &[Token], body: &[SourceLine]) -> Result<(), String> {
+        let (count_tokens, variable) = match arguments {
+            [count @ .., Token::Punct(","), Token::Word(variable)] if is_symbol_name(variable) => {
+                (count, Some(variable))
+            }
+            [.., Token::Punct(","), token] => {
+                return Err(format!("Invalid .rept variable '{token}'"));
+            }
+            _ => (arguments, None),
+        };
+
+        let count = self.evaluate_now(&parse_single_expression(count_tokens)?)?;
+
+        if count < 0 {
+            return Err(format!(".rept count {count} is negative"));
+        }
+
+        let locals = local_labels(body, &[]);
+
+        for iteration in 0..count {
+            self.expansion_count += 1;
+
+            let local_suffix = format!("@{}", self.expansion_count);
+
+            self.expand(format!("iteration {iteration} of .rept"), body, |word| {
+                if variable.is_some_and(|variable| variable == word) {
+                    vec![Token::Word(iteration.to_string())]
+                } else if locals.iter().any(|local| local == word) {
+                    vec![Token::Word(format!("{word}{local_suffix}"))]
+                } else {
+                    vec![Token::Word(word.to_owned())]
                 }
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // Assembles a copy of body where substitute replaces each word, and restores the location
+    fn expand(
+        &mut self,
+        origin: String,
+        body: &[SourceLine],
+        substitute: impl Fn(&str) -> Vec<Token>,
+    ) -> Result<(), String> {
+        let expansion = Rc::new(Expansion {
+            origin,
+            call: self.location.clone(),
+        });
+
+        let lines = body
+            .iter()
+            .map(|line| SourceLine {
+                location: Location {
+                    expansion: Some(Rc::clone(&expansion)),
+                    ..line.location.clone()
+                },
+                tokens: line
+                    .tokens
+                    .as_ref()
+                    .map(|tokens| {
+                        tokens
+                            .iter()
+                            .flat_map(|token| match token {
+                                Token::Word(word) => substitute(word),
+                                _ => vec![token.clone()],
+                            })
+                            .collect()
+                    })
+                    .map_err(Clone::clone),
+            })
+            .collect::<Vec<SourceLine>>();
+
+        self.expansion_depth += 1;
+        self.assemble_lines(&lines)?;
+        self.expansion_depth -= 1;
+
+        self.location = expansion.call.clone();
+
+        Ok(())
+    }
+
+    // Included files are searched next to the including file, then in the include directories
+    fn find_include(&self, path: &str) -> Result<PathBuf, String> {
+        let including_dir = match &self.location.file {
+            Some(file) => file.parent().unwrap_or(Path::new("")),
+            None => &self.source_dir,
+        };
+
+        std::iter::once(including_dir)
+            .chain(self.options.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| format!("Can't find included file '{path}'"))
+    }
+
+    // Evaluates an expression during the first pass, it can only use symbols defined above
+    fn evaluate_now(&self, expression: &Expression) -> Result<i64, String> {
+        evaluate(expression, &self.scope, &self.symbols, &mut Vec::new())
+    }
+}
+
+// Labels defined at the start of a line of body, renamed in each expansion
+fn local_labels(body: &[SourceLine], parameters: &[String]) -> Vec<String> {
+    body.iter()
+        .filter_map(|line| match line.tokens.as_deref() {
+            Ok([Token::Word(label), Token::Punct(":"), ..]) if !parameters.contains(label) => {
+                Some(label.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Conditions of .if and .while are tested when the program runs if they use a register, an
+// expression evaluated by the assembler can't
+fn is_runtime_condition(tokens: &[Token]) -> bool {
+    tokens
+        .iter()
+        .any(|token| matches!(token, Token::Word(word) if is_register_name(word)))
+}
 
-                match generate_instruction(&instruction, &parameters) {
-                    Ok(line_binary) => Ok(Some(line_binary)),
+// Gives the instruction skipping the next one when the condition holds : Vx == value,
+// Vx != value, key Vx (pressed) or !key Vx (not pressed), value being a register or a byte
+fn runtime_condition(tokens: &[Token]) -> Result<(Instruction, Vec<Operand>), String> {
+    let is_key = |word: &str| word.eq_ignore_ascii_case("key");
 
-                    Err(line_error) => Err(line_error),
+    let (instruction, operands) = match tokens {
+        [Token::Word(key), register @ ..] if is_key(key) => (
+            Instruction::Skp,
+            parse_operands(&mut register.iter().peekable())?,
+        ),
+        [Token::Punct("!"), Token::Word(key), register @ ..] if is_key(key) => (
+            Instruction::Sknp,
+            parse_operands(&mut register.iter().peekable())?,
+        ),
+        _ => {
+            let mut tokens = tokens.iter().peekable();
+            // Registers compared with == are whole operands of SEQ or SNE
+            let parse_side = |tokens: &mut Tokens| match tokens.peek() {
+                Some(Token::Word(word)) if is_register_name(word) => {
+                    tokens.next();
+                    parse_parameter(word).map(Operand::Parameter)
                 }
+                _ => parse_operand(tokens),
+            };
+
+            let left = parse_side(&mut tokens)?;
+
+            let instruction = match tokens.next() {
+                Some(Token::Punct("==")) => Instruction::Seq,
+                Some(Token::Punct("!=")) => Instruction::Sne,
+                _ => return Err("Condition on registers needs ==, != or key".to_owned()),
+            };
+
+            let right = parse_side(&mut tokens)?;
+
+            if let Some(token) = tokens.next() {
+                return Err(format!("Unexpected '{token}' after the condition"));
             }
 
-            Err(error) => Err(error),
+            (instruction, vec![left, right])
+        }
+    };
+
+    match operands.first() {
+        Some(Operand::Parameter(Parameter::Register(Register::V(_)))) => {
+            Ok((instruction, operands))
+        }
+        _ => Err("Condition must start with a V register".to_owned()),
+    }
+}
+
+// Address, up to 8 bytes and the statement they come from, followed by its location
+fn listing_line(
+    address: usize,
+    bytes: &[u8],
+    statement: &Statement,
+    location: &Location,
+) -> String {
+    let mut bytes_str = bytes
+        .iter()
+        .take(8)
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    if bytes.len() > 8 {
+        bytes_str.push_str(" ...");
+    }
+
+    format!(
+        "{address:03X}  {bytes_str:<27}  {:<32} ; {location}",
+        statement.to_string()
+    )
+}
+
+fn reserved_count(directive: &str, count: i64) -> Result<usize, String> {
+    if !(0..=0x1000).contains(&count) {
+        return Err(format!(
+            "{directive} count {count} is outside of the 4096 bytes of memory"
+        ));
+    }
+
+    Ok(count as usize)
+}
+
+fn first_word(tokens: &[Token]) -> Option<&str> {
+    match tokens.first() {
+        Some(Token::Word(word)) => Some(word),
+        _ => None,
+    }
+}
+
+// Finds the line closing the block opened at lines[start], nested blocks of the same kind included
+fn find_block_end(
+    lines: &[SourceLine],
+    start: usize,
+    open: &str,
+    close: &str,
+) -> Result<usize, String> {
+    let mut depth = 0;
+
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        match line.tokens.as_deref().ok().and_then(first_word) {
+            Some(word) if word == open => depth += 1,
+            Some(word) if word == close && depth == 0 => return Ok(i),
+            Some(word) if word == close => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Err(format!("{open} without {close}"))
+}
+
+// Macro arguments are separated by commas, or by spaces like instruction operands when there is
+// no comma
+fn split_arguments(tokens: &[Token]) -> Result<Vec<&[Token]>, String> {
+    let mut arguments = Vec::<&[Token]>::new();
+
+    if tokens.is_empty() {
+        return Ok(arguments);
+    }
+
+    if tokens.contains(&Token::Punct(",")) {
+        let mut depth = 0;
+        let mut start = 0;
+
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Punct("(") => depth += 1,
+                Token::Punct(")") => depth -= 1,
+                Token::Punct(",") if depth == 0 => {
+                    arguments.push(&tokens[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
         }
+
+        arguments.push(&tokens[start..]);
     } else {
-        Ok(None)
+        check_spaced_signs(tokens.iter())?;
+
+        let mut rest = tokens.iter().peekable();
+
+        while rest.peek().is_some() {
+            let start = tokens.len() - rest.len();
+            parse_operand(&mut rest)?;
+            arguments.push(&tokens[start..tokens.len() - rest.len()]);
+        }
+    }
+
+    Ok(arguments)
+}
+
+// Parses arguments made of expressions separated by commas, refusing 3 -1 which misses one
+fn parse_expression_list(tokens: &[Token]) -> Result<Vec<Expression>, String> {
+    check_spaced_signs(tokens.iter())?;
+
+    let mut tokens = tokens.iter().peekable();
+    let mut expressions = Vec::<Expression>::new();
+
+    while tokens.peek().is_some() {
+        let expression = parse_expression(&mut tokens)?;
+
+        match tokens.next() {
+            None | Some(Token::Punct(",")) => expressions.push(expression),
+            Some(token) => return Err(format!("Unexpected '{token}' after '{expression}'")),
+        }
+    }
+
+    Ok(expressions)
+}
+
+// Parses arguments made of exactly one expression
+fn parse_single_expression(tokens: &[Token]) -> Result<Expression, String> {
+    let mut tokens = tokens.iter().peekable();
+    let expression = parse_expression(&mut tokens)?;
+
+    if let Some(token) = tokens.next() {
+        return Err(format!("Unexpected '{token}' after '{expression}'"));
+    }
+
+    Ok(expression)
+}
+
+fn generate_statement(
+    statement: &Statement,
+    scope: &str,
+    symbols: &Symbols,
+    binary: &mut Vec<u8>,
+) -> Result<(), String> {
+    match statement {
+        Statement::Instruction(instruction, operands) => {
+            let parameters = resolve_operands(operands, scope, symbols)?;
+            let opcode = generate_instruction(instruction, &parameters)?;
+
+            binary.extend(opcode.to_be_bytes());
+        }
+
+        Statement::Bytes(operands) => {
+            for parameter in resolve_operands(operands, scope, symbols)? {
+                let n = data_value(&parameter)?;
+
+                if !(-0x80..=0xFF).contains(&n) {
+                    return Err(format!("Value {n} doesn't fit in a byte"));
+                }
+                binary.push(n as u8);
+            }
+        }
+
+        Statement::Words(operands) => {
+            for parameter in resolve_operands(operands, scope, symbols)? {
+                let n = data_value(&parameter)?;
+
+                if !(-0x8000..=0xFFFF).contains(&n) {
+                    return Err(format!("Value {n} doesn't fit in a word"));
+                }
+                binary.extend((n as u16).to_be_bytes());
+            }
+        }
+
+        Statement::Fill(count, value) | Statement::Space(count, value) => {
+            binary.extend(std::iter::repeat_n(*value, *count));
+        }
+
+        Statement::Raw(bytes) => {
+            binary.extend(bytes);
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_operands(
+    operands: &[Operand],
+    scope: &str,
+    symbols: &Symbols,
+) -> Result<Vec<Parameter>, String> {
+    let mut parameters = Vec::<Parameter>::new();
+
+    for operand in operands {
+        match operand {
+            Operand::Parameter(parameter) => parameters.push(*parameter),
+            Operand::Expression(expression) => {
+                let n = evaluate(expression, scope, symbols, &mut Vec::new())?;
+                parameters.push(Parameter::Number(n));
+            }
+        }
+    }
+
+    Ok(parameters)
+}
+
+// resolving holds the constants being evaluated, to detect definitions that depend on themselves
+fn evaluate(
+    expression: &Expression,
+    scope: &str,
+    symbols: &Symbols,
+    resolving: &mut Vec<String>,
+) -> Result<i64, String> {
+    expression.evaluate(&mut |name| match find_symbol(symbols, scope, name) {
+        Some((_, (Symbol::Label(address), _))) => Ok(*address as i64),
+        Some((full_name, (Symbol::Constant(value, constant_scope), _))) => {
+            if resolving.contains(full_name) {
+                return Err(format!("Constant '{name}' is defined in terms of itself"));
+            }
+
+            resolving.push(full_name.clone());
+            let n = evaluate(value, constant_scope, symbols, resolving)?;
+            resolving.pop();
+
+            Ok(n)
+        }
+        Some((_, (Symbol::Register(_), _))) => Err(format!(
+            "Register alias '{name}' can't be used in an expression"
+        )),
+        Some((_, (Symbol::SectionLabel(_, _), _))) => Err(format!(
+            "Label '{name}' has no address before the sections are placed"
+        )),
+        Some((_, (Symbol::Variable(_, _), _))) => Err(format!(
+            "Variable '{name}' has no address before the end of the program"
+        )),
+        None => Err(format!("Undefined symbol '{name}'")),
+    })
+}
+
+// Looks for name in the scope, then in the scopes around it up to the global one
+fn find_symbol<'a>(
+    symbols: &'a Symbols,
+    scope: &str,
+    name: &str,
+) -> Option<(&'a String, &'a (Symbol, Location))> {
+    let mut prefix = scope;
+
+    loop {
+        if let Some(entry) = symbols.get_key_value(&format!("{prefix}{name}")) {
+            return Some(entry);
+        }
+        if prefix.is_empty() {
+            return None;
+        }
+
+        let parent_end = prefix[..prefix.len() - 1].rfind('.').map_or(0, |i| i + 1);
+        prefix = &prefix[..parent_end];
     }
 }
 
+fn data_value(parameter: &Parameter) -> Result<i64, String> {
+    match *parameter {
+        Parameter::Register(_) => Err("Value can't be a register".to_owned()),
+        Parameter::Address(n) => Ok(n as i64),
+        Parameter::Byte(n) | Parameter::Nibble(n) => Ok(n as i64),
+        Parameter::Number(n) => Ok(n),
+    }
+}
+
+// Mnemonics are case-insensitive
 fn parse_instruction(word: &str) -> Result<Instruction, String> {
-    match word {
+    match word.to_ascii_uppercase().as_str() {
+        "SYS" => Ok(Instruction::Sys),
         "CLS" => Ok(Instruction::Cls),
         "RET" => Ok(Instruction::Ret),
         "JMP" => Ok(Instruction::Jmp),
@@ -84,25 +1937,324 @@ fn parse_instruction(word: &str) -> Result<Instruction, String> {
     }
 }
 
+// Pseudo-instructions and their operands, they expand to several opcodes and use VF as scratch
+// register, the README documents them for users :
+//   SGT Vx, Vy       skips the next instruction if Vx > Vy : SUB Vy Vx, ADD Vy Vx, SNE VF 0
+//   SLT Vx, Vy       skips the next instruction if Vx < Vy : SUB Vx Vy, ADD Vx Vy, SNE VF 0
+//   NOT Vx           Vx = ~Vx : LD VF 0xFF, XOR Vx VF
+//   NEG Vx           Vx = -Vx : LD VF 0, SUBN Vx VF
+//   ADD16 Vx, Vy     adds Vy:Vy+1 to Vx:Vx+1, high byte first : ADD Vx Vy, ADD Vx+1 Vy+1, ADD Vx VF
+//   ADD16 Vx, value  adds a 16 bits value to Vx:Vx+1 : ADD Vx hi(value), LD VF lo(value),
+//                    ADD Vx+1 VF, ADD Vx VF
+// LD I address + Vx is expanded too, to LD I address, ADD I Vx, it keeps VF
+const PSEUDO_INSTRUCTIONS: [(&str, &str); 5] = [
+    ("SGT", "Vx, Vy"),
+    ("SLT", "Vx, Vy"),
+    ("NOT", "Vx"),
+    ("NEG", "Vx"),
+    ("ADD16", "Vx, Vy or Vx, value"),
+];
+
+type Opcodes = Vec<(Instruction, Vec<Operand>)>;
+
+fn expand_pseudo_instruction(word: &str, arguments: &[Token]) -> Result<Option<Opcodes>, String> {
+    const COMMA: &[Token] = &[Token::Punct(",")];
+
+    let mnemonic = word.to_ascii_uppercase();
+    let v = |x: u8| Operand::Parameter(Parameter::Register(Register::V(x)));
+    let number = |n: i64| Operand::Expression(Expression::Number(n));
+
+    if mnemonic == "LD" {
+        return match arguments {
+            [Token::Word(i), address @ .., Token::Punct("+" | " +"), Token::Word(register)]
+                if i.eq_ignore_ascii_case("I")
+                    && matches!(
+                        parse_parameter(register),
+                        Ok(Parameter::Register(Register::V(_)))
+                    ) =>
+            {
+                let address = address.strip_prefix(COMMA).unwrap_or(address);
+                let i = Operand::Parameter(Parameter::Register(Register::I));
+
+                Ok(Some(vec![
+                    (
+                        Instruction::Ld,
+                        vec![
+                            i.clone(),
+                            Operand::Expression(parse_single_expression(address)?),
+                        ],
+                    ),
+                    (
+                        Instruction::Add,
+                        vec![i, Operand::Parameter(parse_parameter(register)?)],
+                    ),
+                ]))
+            }
+            _ => Ok(None),
+        };
+    }
+
+    let Some((_, usage)) = PSEUDO_INSTRUCTIONS
+        .iter()
+        .find(|(pseudo_mnemonic, _)| *pseudo_mnemonic == mnemonic)
+    else {
+        return Ok(None);
+    };
+
+    let operands = parse_operands(&mut arguments.iter().peekable())?;
+    let register = |operand: &Operand| pseudo_register(&mnemonic, operand);
+
+    let expansion = match (mnemonic.as_str(), &operands[..]) {
+        ("SGT" | "SLT", [x, y]) => {
+            let (x, y) = (register(x)?, register(y)?);
+
+            if x == y {
+                return Err(format!("{mnemonic} needs two different registers"));
+            }
+
+            let (left, right) = if mnemonic == "SLT" { (x, y) } else { (y, x) };
+
+            // The borrow of the subtraction is lost, the carry of adding back tells left < right
+            vec![
+                (Instruction::Sub, vec![v(left), v(right)]),
+                (Instruction::Add, vec![v(left), v(right)]),
+                (Instruction::Sne, vec![v(0xF), number(0)]),
+            ]
+        }
+
+        ("NOT", [x]) => vec![
+            (Instruction::Ld, vec![v(0xF), number(0xFF)]),
+            (Instruction::Xor, vec![v(register(x)?), v(0xF)]),
+        ],
+
+        ("NEG", [x]) => vec![
+            (Instruction::Ld, vec![v(0xF), number(0)]),
+            (Instruction::Subn, vec![v(register(x)?), v(0xF)]),
+        ],
+
+        ("ADD16", [x, y @ Operand::Parameter(_)]) => {
+            let (x, y) = (register(x)?, register(y)?);
+
+            if x == 0xE || y == 0xE {
+                return Err("ADD16 register pairs can't end with VF".to_owned());
+            }
+            if x != y && x.abs_diff(y) < 2 {
+                return Err("ADD16 register pairs can't overlap".to_owned());
+            }
+
+            vec![
+                (Instruction::Add, vec![v(x), v(y)]),
+                (Instruction::Add, vec![v(x + 1), v(y + 1)]),
+                (Instruction::Add, vec![v(x), v(0xF)]),
+            ]
+        }
+
+        ("ADD16", [x, Operand::Expression(value)]) => {
+            let x = register(x)?;
+
+            if x == 0xE {
+                return Err("ADD16 register pairs can't end with VF".to_owned());
+            }
+
+            let byte = |function| {
+                Operand::Expression(Expression::Function(function, Box::new(value.clone())))
+            };
+
+            vec![
+                (Instruction::Add, vec![v(x), byte("hi")]),
+                (Instruction::Ld, vec![v(0xF), byte("lo")]),
+                (Instruction::Add, vec![v(x + 1), v(0xF)]),
+                (Instruction::Add, vec![v(x), v(0xF)]),
+            ]
+        }
+
+        _ => return Err(format!("{mnemonic} takes {usage}, and clobbers VF")),
+    };
+
+    Ok(Some(expansion))
+}
+
+// VF can't be an operand of the pseudo-instructions that clobber it
+fn pseudo_register(mnemonic: &str, operand: &Operand) -> Result<u8, String> {
+    match operand {
+        Operand::Parameter(Parameter::Register(Register::V(0xF))) => {
+            Err(format!("{mnemonic} clobbers VF, it can't be an operand"))
+        }
+        Operand::Parameter(Parameter::Register(Register::V(x))) => Ok(*x),
+        _ => Err(format!("{mnemonic} operands must be V registers")),
+    }
+}
+
+fn cowgod_mnemonic(word: &str) -> Result<Instruction, String> {
+    match word.to_ascii_uppercase().as_str() {
+        "SE" => Ok(Instruction::Seq),
+        "JP" => Ok(Instruction::Jmp),
+        "SYS" | "CLS" | "RET" | "CALL" | "SNE" | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB"
+        | "SHR" | "SUBN" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" => parse_instruction(word),
+        _ => Err("Unknown instruction name".to_owned()),
+    }
+}
+
+// Cowgod's forms that are instructions of their own in the native dialect are recognized by
+// their special operands : V0 for JP, F, B, [I] and K for LD
+fn parse_cowgod_instruction(
+    word: &str,
+    arguments: &[Token],
+) -> Result<(Instruction, Vec<Operand>), String> {
+    const COMMA: &[Token] = &[Token::Punct(",")];
+
+    let instruction = cowgod_mnemonic(word)?;
+
+    let is_i_pointer = |tokens: &[Token]| matches!(tokens, [Token::Punct("["), Token::Word(i), Token::Punct("]")] if i.eq_ignore_ascii_case("I"));
+
+    let (instruction, operands) = match (instruction, arguments) {
+        (Instruction::Jmp, [Token::Word(v0), rest @ ..])
+            if v0.eq_ignore_ascii_case("V0") && !rest.is_empty() =>
+        {
+            (Instruction::Jmpo, rest.strip_prefix(COMMA).unwrap_or(rest))
+        }
+        (Instruction::Ld, [Token::Word(f), rest @ ..]) if f.eq_ignore_ascii_case("F") => {
+            (Instruction::Spr, rest.strip_prefix(COMMA).unwrap_or(rest))
+        }
+        (Instruction::Ld, [Token::Word(b), rest @ ..]) if b.eq_ignore_ascii_case("B") => {
+            (Instruction::Bcd, rest.strip_prefix(COMMA).unwrap_or(rest))
+        }
+        (Instruction::Ld, _) if arguments.len() > 3 && is_i_pointer(&arguments[..3]) => {
+            let rest = &arguments[3..];
+            (Instruction::Stn, rest.strip_prefix(COMMA).unwrap_or(rest))
+        }
+        (Instruction::Ld, _)
+            if arguments.len() > 3 && is_i_pointer(&arguments[arguments.len() - 3..]) =>
+        {
+            let rest = &arguments[..arguments.len() - 3];
+            (Instruction::Ldn, rest.strip_suffix(COMMA).unwrap_or(rest))
+        }
+        (Instruction::Ld, [rest @ .., Token::Word(k)])
+            if k.eq_ignore_ascii_case("K") && !rest.is_empty() =>
+        {
+            (Instruction::Ldk, rest.strip_suffix(COMMA).unwrap_or(rest))
+        }
+        (instruction, arguments) => (instruction, arguments),
+    };
+
+    let mut operands = parse_instruction_operands(operands)?;
+
+    // SHR Vx and SHL Vx shift Vx in place, whichever register the interpreter reads
+    if let (Instruction::Shr | Instruction::Shl, [Operand::Parameter(register)]) =
+        (&instruction, &operands[..])
+    {
+        let register = *register;
+        operands.push(Operand::Parameter(register));
+    }
+
+    Ok((instruction, operands))
+}
+
+// Operands are separated by commas or spaces, an expression goes on as long as it is followed by
+// a binary operator
+fn parse_operands(tokens: &mut Tokens) -> Result<Vec<Operand>, String> {
+    let mut operands = Vec::<Operand>::new();
+
+    check_spaced_signs(tokens.clone())?;
+
+    while tokens.peek().is_some() {
+        operands.push(parse_operand(tokens)?);
+
+        if tokens.peek() == Some(&&Token::Punct(",")) {
+            tokens.next();
+        }
+    }
+
+    Ok(operands)
+}
+
+// I, DT and ST are registers when they are a whole operand of an instruction, and symbols
+// anywhere else
+fn parse_instruction_operands(tokens: &[Token]) -> Result<Vec<Operand>, String> {
+    parse_operands(&mut tokens.iter().peekable())?
+        .into_iter()
+        .map(register_operand)
+        .collect()
+}
+
+fn register_operand(operand: Operand) -> Result<Operand, String> {
+    match operand {
+        Operand::Expression(Expression::Symbol(name)) if is_register_name(&name) => {
+            parse_parameter(&name).map(Operand::Parameter)
+        }
+        operand => Ok(operand),
+    }
+}
+
+// Without commas, 0x10 -0x20 could be 0x10 - 0x20 or two values
+fn check_spaced_signs<'a>(tokens: impl Iterator<Item = &'a Token> + Clone) -> Result<(), String> {
+    if tokens.clone().any(|token| *token == Token::Punct(",")) {
+        return Ok(());
+    }
+
+    for token in tokens {
+        if let Token::Punct(sign) = token {
+            if SPACED_SIGNS.contains(sign) {
+                return Err(format!(
+                    "'{token}' after a space is ambiguous between two operands, separate them with commas or write 'a {token} b'"
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_operand(tokens: &mut Tokens) -> Result<Operand, String> {
+    if let Some(Token::Word(word)) = tokens.peek() {
+        let mut lookahead = tokens.clone();
+        lookahead.next();
+        let in_expression = lookahead
+            .peek()
+            .is_some_and(|token| is_binary_operator(token));
+
+        // V registers, and tagged numbers that are not part of an expression, keep their type
+        if is_v_register_name(word)
+            || (!in_expression && !is_symbol_reference(word) && parse_number(word).is_none())
+        {
+            tokens.next();
+            return parse_parameter(word).map(Operand::Parameter);
+        }
+    }
+
+    parse_expression(tokens).map(Operand::Expression)
+}
+
+// Register names are case-insensitive, V registers are numbered in decimal (V15) or hexadecimal
+// (VF)
 fn parse_parameter(word: &str) -> Result<Parameter, String> {
-    if let Some(n_str) = word.strip_prefix('V') {
+    let register_str = word.to_ascii_uppercase();
+
+    check_type_suffix(word)?;
+
+    if let Some(n_str) = register_str.strip_prefix('V') {
         if let Ok(n) = n_str.parse::<u64>() {
             if n < 16 {
                 Ok(Parameter::Register(Register::V(n as u8)))
             } else {
                 Err("There are only 16 V register".to_owned())
             }
+        } else if let Some(n) = ["A", "B", "C", "D", "E", "F"]
+            .iter()
+            .position(|n| *n == n_str)
+        {
+            Ok(Parameter::Register(Register::V(10 + n as u8)))
         } else {
             Err("Wrong V register name".to_owned())
         }
-    } else if word == "I" {
+    } else if register_str == "I" {
         Ok(Parameter::Register(Register::I))
-    } else if word == "DT" {
+    } else if register_str == "DT" {
         Ok(Parameter::Register(Register::DT))
-    } else if word == "ST" {
+    } else if register_str == "ST" {
         Ok(Parameter::Register(Register::ST))
     } else if let Some(n_str) = word.strip_suffix('A') {
-        if let Ok(n) = n_str.parse::<u64>() {
+        if let Some(n) = parse_number(n_str) {
             if n < 4096 {
                 Ok(Parameter::Address(n as u16))
             } else {
@@ -112,7 +2264,7 @@ fn parse_parameter(word: &str) -> Result<Parameter, String> {
             Err("Can't parse Address".to_owned())
         }
     } else if let Some(n_str) = word.strip_suffix('B') {
-        if let Ok(n) = n_str.parse::<u64>() {
+        if let Some(n) = parse_number(n_str) {
             if n < 256 {
                 Ok(Parameter::Byte(n as u8))
             } else {
@@ -122,7 +2274,7 @@ fn parse_parameter(word: &str) -> Result<Parameter, String> {
             Err("Can't parse Byte".to_owned())
         }
     } else if let Some(n_str) = word.strip_suffix('N') {
-        if let Ok(n) = n_str.parse::<u64>() {
+        if let Some(n) = parse_number(n_str) {
             if n < 16 {
                 Ok(Parameter::Nibble(n as u8))
             } else {
@@ -1,173 +1,1316 @@
 #![cfg(test)]
 
-use crate::parser::parse;
+use std::path::{Path, PathBuf};
+
+use crate::parser::{assemble, Dialect, Options};
+
+fn parse(text: &str) -> Result<Vec<u8>, String> {
+    assemble_binary(text, None, &Options::default())
+}
+
+fn assemble_binary(text: &str, path: Option<&Path>, options: &Options) -> Result<Vec<u8>, String> {
+    assemble(text, path, options).map(|assembly| assembly.binary)
+}
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/tests/fixtures")
+        .join(name)
+}
+
+fn assemble_fixture(name: &str, options: &Options) -> Result<Vec<u8>, String> {
+    let path = fixture(name);
+    let text = std::fs::read_to_string(&path).unwrap();
+
+    assemble_binary(&text, Some(&path), options)
+}
 
 #[test]
 fn test_cls() {
-    assert_eq!(parse("CLS"), Ok(vec![0x00E0]));
+    assert_eq!(parse("CLS"), Ok(vec![0x00, 0xE0]));
 }
 
 #[test]
 fn test_ret() {
-    assert_eq!(parse("RET"), Ok(vec![0x00EE]));
+    assert_eq!(parse("RET"), Ok(vec![0x00, 0xEE]));
 }
 
 #[test]
 fn test_jmp() {
-    assert_eq!(parse("JMP 1234A"), Ok(vec![0x14D2]));
+    assert_eq!(parse("JMP 1234A"), Ok(vec![0x14, 0xD2]));
 }
 
 #[test]
 fn test_call() {
-    assert_eq!(parse("CALL 1234A"), Ok(vec![0x24D2]));
+    assert_eq!(parse("CALL 1234A"), Ok(vec![0x24, 0xD2]));
 }
 
 #[test]
 fn test_seq_v_nn() {
-    assert_eq!(parse("SEQ V12 42B"), Ok(vec![0x3C2A]));
+    assert_eq!(parse("SEQ V12 42B"), Ok(vec![0x3C, 0x2A]));
 }
 
 #[test]
 fn test_sne_v_nn() {
-    assert_eq!(parse("SNE V12 42B"), Ok(vec![0x4C2A]));
+    assert_eq!(parse("SNE V12 42B"), Ok(vec![0x4C, 0x2A]));
 }
 
 #[test]
 fn test_seq_v_v() {
-    assert_eq!(parse("SEQ V6 V12"), Ok(vec![0x56C0]));
+    assert_eq!(parse("SEQ V6 V12"), Ok(vec![0x56, 0xC0]));
 }
 
 #[test]
 fn test_ld_v_nn() {
-    assert_eq!(parse("LD V12 42B"), Ok(vec![0x6C2A]));
+    assert_eq!(parse("LD V12 42B"), Ok(vec![0x6C, 0x2A]));
 }
 
 #[test]
 fn test_add_v_nn() {
-    assert_eq!(parse("ADD V12 42B"), Ok(vec![0x7C2A]));
+    assert_eq!(parse("ADD V12 42B"), Ok(vec![0x7C, 0x2A]));
 }
 
 #[test]
 fn test_ld_v_v() {
-    assert_eq!(parse("LD V6 V12"), Ok(vec![0x86C0]));
+    assert_eq!(parse("LD V6 V12"), Ok(vec![0x86, 0xC0]));
 }
 
 #[test]
 fn test_or_v_v() {
-    assert_eq!(parse("OR V6 V12"), Ok(vec![0x86C1]));
+    assert_eq!(parse("OR V6 V12"), Ok(vec![0x86, 0xC1]));
 }
 
 #[test]
 fn test_and_v_v() {
-    assert_eq!(parse("AND V6 V12"), Ok(vec![0x86C2]));
+    assert_eq!(parse("AND V6 V12"), Ok(vec![0x86, 0xC2]));
 }
 
 #[test]
 fn test_xor_v_v() {
-    assert_eq!(parse("XOR V6 V12"), Ok(vec![0x86C3]));
+    assert_eq!(parse("XOR V6 V12"), Ok(vec![0x86, 0xC3]));
 }
 
 #[test]
 fn test_add_v_v() {
-    assert_eq!(parse("ADD V6 V12"), Ok(vec![0x86C4]));
+    assert_eq!(parse("ADD V6 V12"), Ok(vec![0x86, 0xC4]));
 }
 
 #[test]
 fn test_sub_v_v() {
-    assert_eq!(parse("SUB V6 V12"), Ok(vec![0x86C5]));
+    assert_eq!(parse("SUB V6 V12"), Ok(vec![0x86, 0xC5]));
 }
 
 #[test]
 fn test_shr_v_v() {
-    assert_eq!(parse("SHR V6 V12"), Ok(vec![0x86C6]));
+    assert_eq!(parse("SHR V6 V12"), Ok(vec![0x86, 0xC6]));
 }
 
 #[test]
 fn test_subn_v_v() {
-    assert_eq!(parse("SUBN V6 V12"), Ok(vec![0x86C7]));
+    assert_eq!(parse("SUBN V6 V12"), Ok(vec![0x86, 0xC7]));
 }
 
 #[test]
 fn test_shl_v_v() {
-    assert_eq!(parse("SHL V6 V12"), Ok(vec![0x86CE]));
+    assert_eq!(parse("SHL V6 V12"), Ok(vec![0x86, 0xCE]));
 }
 
 #[test]
 fn test_sne_v_v() {
-    assert_eq!(parse("SNE V6 V12"), Ok(vec![0x96C0]));
+    assert_eq!(parse("SNE V6 V12"), Ok(vec![0x96, 0xC0]));
 }
 
 #[test]
 fn test_ld_i_nnn() {
-    assert_eq!(parse("LD I 1234A"), Ok(vec![0xA4D2]));
+    assert_eq!(parse("LD I 1234A"), Ok(vec![0xA4, 0xD2]));
 }
 
 #[test]
 fn test_jmpo_nnn() {
-    assert_eq!(parse("JMPO 1234A"), Ok(vec![0xB4D2]));
+    assert_eq!(parse("JMPO 1234A"), Ok(vec![0xB4, 0xD2]));
 }
 
 #[test]
 fn test_rnd_v_nn() {
-    assert_eq!(parse("RND V12 42B"), Ok(vec![0xCC2A]));
+    assert_eq!(parse("RND V12 42B"), Ok(vec![0xCC, 0x2A]));
 }
 
 #[test]
 fn test_drw_v_v_n() {
-    assert_eq!(parse("DRW V6 V12 15N"), Ok(vec![0xD6CF]));
+    assert_eq!(parse("DRW V6 V12 15N"), Ok(vec![0xD6, 0xCF]));
 }
 
 #[test]
 fn test_skp_v() {
-    assert_eq!(parse("SKP V12"), Ok(vec![0xEC9E]));
+    assert_eq!(parse("SKP V12"), Ok(vec![0xEC, 0x9E]));
 }
 
 #[test]
 fn test_sknp_v() {
-    assert_eq!(parse("SKNP V12"), Ok(vec![0xECA1]));
+    assert_eq!(parse("SKNP V12"), Ok(vec![0xEC, 0xA1]));
 }
 
 #[test]
 fn test_ld_v_dt() {
-    assert_eq!(parse("LD V12 DT"), Ok(vec![0xFC07]));
+    assert_eq!(parse("LD V12 DT"), Ok(vec![0xFC, 0x07]));
 }
 
 #[test]
 fn test_ldk_v() {
-    assert_eq!(parse("LDK V12"), Ok(vec![0xFC0A]));
+    assert_eq!(parse("LDK V12"), Ok(vec![0xFC, 0x0A]));
 }
 
 #[test]
 fn test_ld_dt_v() {
-    assert_eq!(parse("LD DT V12"), Ok(vec![0xFC15]));
+    assert_eq!(parse("LD DT V12"), Ok(vec![0xFC, 0x15]));
 }
 
 #[test]
 fn test_ld_st_v() {
-    assert_eq!(parse("LD ST V12"), Ok(vec![0xFC18]));
+    assert_eq!(parse("LD ST V12"), Ok(vec![0xFC, 0x18]));
 }
 
 #[test]
 fn test_add_i_v() {
-    assert_eq!(parse("ADD I V12"), Ok(vec![0xFC1E]));
+    assert_eq!(parse("ADD I V12"), Ok(vec![0xFC, 0x1E]));
 }
 
 #[test]
 fn test_spr_v() {
-    assert_eq!(parse("SPR V12"), Ok(vec![0xFC29]));
+    assert_eq!(parse("SPR V12"), Ok(vec![0xFC, 0x29]));
 }
 
 #[test]
 fn test_bcd_v() {
-    assert_eq!(parse("BCD V12"), Ok(vec![0xFC33]));
+    assert_eq!(parse("BCD V12"), Ok(vec![0xFC, 0x33]));
 }
 
 #[test]
 fn test_stn_v() {
-    assert_eq!(parse("STN V12"), Ok(vec![0xFC55]));
+    assert_eq!(parse("STN V12"), Ok(vec![0xFC, 0x55]));
 }
 
 #[test]
 fn test_ldn_v() {
-    assert_eq!(parse("LDN V12"), Ok(vec![0xFC65]));
+    assert_eq!(parse("LDN V12"), Ok(vec![0xFC, 0x65]));
+}
+
+#[test]
+fn test_label_backward() {
+    assert_eq!(
+        parse("CLS\nloop: ADD V1 1B\nJMP loop"),
+        Ok(vec![0x00, 0xE0, 0x71, 0x01, 0x12, 0x02])
+    );
+}
+
+#[test]
+fn test_label_forward() {
+    assert_eq!(
+        parse("CALL sub\nJMPO sub\nLD I sub\nsub:\nRET"),
+        Ok(vec![0x22, 0x06, 0xB2, 0x06, 0xA2, 0x06, 0x00, 0xEE])
+    );
+}
+
+#[test]
+fn test_label_undefined() {
+    assert_eq!(
+        parse("CLS\nJMP nowhere"),
+        Err("Error line 2 : Undefined symbol 'nowhere'".to_owned())
+    );
+}
+
+#[test]
+fn test_label_duplicate() {
+    assert_eq!(
+        parse("loop: CLS\nloop: RET"),
+        Err("Error line 2 : Label 'loop' is already defined line 1".to_owned())
+    );
+}
+
+#[test]
+fn test_hexadecimal_literals() {
+    assert_eq!(
+        parse("JMP 0x4D2\nLD V1 $1F\nDRW V1 V2 #F"),
+        Ok(vec![0x14, 0xD2, 0x61, 0x1F, 0xD1, 0x2F])
+    );
+}
+
+#[test]
+fn test_binary_literals() {
+    assert_eq!(
+        parse("LD V1 0b0101B\nLD V1 %11110000B"),
+        Ok(vec![0x61, 0x05, 0x61, 0xF0])
+    );
+}
+
+#[test]
+fn test_character_literals() {
+    assert_eq!(
+        parse("LD V1 'A'B\nLD V1 ' 'B\nLD V1 ';'B ; comment"),
+        Ok(vec![0x61, 0x41, 0x61, 0x20, 0x61, 0x3B])
+    );
+}
+
+#[test]
+fn test_hexadecimal_type_suffix() {
+    // A and B are hexadecimal digits, so 0x, $ and # literals are always untyped
+    assert_eq!(
+        parse("JMP 0x2AB\nLD V1 $FB\nLD V2 #AB\nDB 0x1B"),
+        Ok(vec![0x12, 0xAB, 0x61, 0xFB, 0x62, 0xAB, 0x1B])
+    );
+    assert_eq!(
+        parse("DRW V1 V2 #FN"),
+        Err(
+            "Error line 1 : Hexadecimal literal '#FN' can't have a type suffix, leave it untyped"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse("DB 0x1N + 1"),
+        Err(
+            "Error line 1 : Hexadecimal literal '0x1N' can't have a type suffix, leave it untyped"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse("DRW V1 V2 15N\nLD V1 %1111B"),
+        Ok(vec![0xD1, 0x2F, 0x61, 0x0F])
+    );
+}
+
+#[test]
+fn test_literal_out_of_range() {
+    assert_eq!(
+        parse("LD V1 %100000000B"),
+        Err("Error line 1 : Byte can only take values up to 255".to_owned())
+    );
+}
+
+#[test]
+fn test_untagged_numbers() {
+    assert_eq!(
+        parse("JMP 1234\nLD V12 42\nDRW V6 V12 15\nLD I 0x2A"),
+        Ok(vec![0x14, 0xD2, 0x6C, 0x2A, 0xD6, 0xCF, 0xA0, 0x2A])
+    );
+}
+
+#[test]
+fn test_untagged_number_out_of_range() {
+    assert_eq!(
+        parse("DRW V6 V12 16"),
+        Err("Error line 1 : Value 16 doesn't fit in n (4 bits)".to_owned())
+    );
+    assert_eq!(
+        parse("LD V1 256"),
+        Err("Error line 1 : Value 256 doesn't fit in nn (8 bits)".to_owned())
+    );
+    assert_eq!(
+        parse("CALL 4096"),
+        Err("Error line 1 : Value 4096 doesn't fit in nnn (12 bits)".to_owned())
+    );
+}
+
+#[test]
+fn test_data_bytes() {
+    assert_eq!(
+        parse("DB 0xF0, 0x90 0xF0\n.byte 'A'"),
+        Ok(vec![0xF0, 0x90, 0xF0, 0x41])
+    );
+}
+
+#[test]
+fn test_data_signs() {
+    assert_eq!(
+        parse("DB 0x10, -0x20, 0x30\nDB 0x10 - 0x20 0x30\nDB 0x10-0x20"),
+        Ok(vec![0x10, 0xE0, 0x30, 0xF0, 0x30, 0xF0])
+    );
+    assert_eq!(
+        parse("DB 0x10 -0x20 0x30"),
+        Err("Error line 1 : '-' after a space is ambiguous between two operands, separate them with commas or write 'a - b'".to_owned())
+    );
+    assert_eq!(
+        parse("DB 1 +1"),
+        Err("Error line 1 : '+' after a space is ambiguous between two operands, separate them with commas or write 'a + b'".to_owned())
+    );
+    // A single signed value after the mnemonic or a macro name isn't ambiguous
+    assert_eq!(
+        parse("DB -1\nDW -1\nvalue: DB -1\n.macro m value\nDB value\n.endm\nm -1"),
+        Ok(vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF])
+    );
+}
+
+#[test]
+fn test_data_words() {
+    assert_eq!(
+        parse("DW 0x1234, 5\n.word table\ntable:"),
+        Ok(vec![0x12, 0x34, 0x00, 0x05, 0x02, 0x06])
+    );
+}
+
+#[test]
+fn test_data_interleaved_with_instructions() {
+    assert_eq!(
+        parse("LD I sprite\nJMP start\nsprite: DB 0x80\nstart: DRW V0 V1 1"),
+        Ok(vec![0xA2, 0x04, 0x12, 0x05, 0x80, 0xD0, 0x11])
+    );
+}
+
+#[test]
+fn test_data_out_of_range() {
+    assert_eq!(
+        parse("DB 1 256"),
+        Err("Error line 1 : Value 256 doesn't fit in a byte".to_owned())
+    );
+}
+
+#[test]
+fn test_constants() {
+    assert_eq!(
+        parse("HEIGHT EQU 5\n.define X 0x20\nLD V1 X\nDRW V1 V1 HEIGHT\nLD I X"),
+        Ok(vec![0x61, 0x20, 0xD1, 0x15, 0xA0, 0x20])
+    );
+}
+
+#[test]
+fn test_constant_used_before_definition() {
+    assert_eq!(
+        parse("LD V1 KEY\nKEY EQU START\nSTART EQU 0xA"),
+        Ok(vec![0x61, 0x0A])
+    );
+}
+
+#[test]
+fn test_constant_redefinition() {
+    assert_eq!(
+        parse("X EQU 1\n.define X 2"),
+        Err("Error line 2 : Constant 'X' is already defined line 1".to_owned())
+    );
+}
+
+#[test]
+fn test_constant_defined_in_terms_of_itself() {
+    assert_eq!(
+        parse("A EQU B\nB EQU A\nLD V1 A"),
+        Err("Error line 3 : Constant 'A' is defined in terms of itself".to_owned())
+    );
+}
+
+#[test]
+fn test_expression_precedence() {
+    assert_eq!(
+        parse("SCREEN_W EQU 64\nLD V1 (SCREEN_W - 8) / 2\nLD V2 2 + 3 * 4\nLD V3 1 << 2 | 1"),
+        Ok(vec![0x61, 0x1C, 0x62, 0x0E, 0x63, 0x05])
+    );
+}
+
+#[test]
+fn test_expression_with_labels() {
+    assert_eq!(
+        parse("LD I table + 5*3\nLD V0 hi(table)\nLD V1 lo(table)\ntable:"),
+        Ok(vec![0xA2, 0x15, 0x60, 0x02, 0x61, 0x06])
+    );
+}
+
+#[test]
+fn test_expression_bit_operations() {
+    assert_eq!(
+        parse("LD V1 ~0x0F & 0xFF\nLD V2 0xF0 >> 4 ^ 0b11\nADD V3 -1"),
+        Ok(vec![0x61, 0xF0, 0x62, 0x0C, 0x73, 0xFF])
+    );
+}
+
+#[test]
+fn test_expression_operands_separated_by_commas() {
+    assert_eq!(parse("DB 1, -1, 2 * 3"), Ok(vec![0x01, 0xFF, 0x06]));
+}
+
+#[test]
+fn test_expression_errors() {
+    assert_eq!(
+        parse("LD V1 2 + 8 / (3 - 3)"),
+        Err("Error line 1 : Division by zero in '8 / (3 - 3)'".to_owned())
+    );
+    assert_eq!(
+        parse("LD V1 (1 << 62) * 4 + 1"),
+        Err("Error line 1 : Overflow in '(1 << 62) * 4'".to_owned())
+    );
+    assert_eq!(
+        parse("LD V1 (2 + 3"),
+        Err("Error line 1 : Missing ')' after '2 + 3'".to_owned())
+    );
+    assert_eq!(
+        parse("LD V1 200 + 100"),
+        Err("Error line 1 : Value 300 doesn't fit in nn (8 bits)".to_owned())
+    );
+}
+
+#[test]
+fn test_org_pads_the_gap() {
+    assert_eq!(
+        parse("JMP start\n.org 0x206\nstart: CLS"),
+        Ok(vec![0x12, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0])
+    );
+}
+
+#[test]
+fn test_org_backwards() {
+    assert_eq!(
+        parse("CLS\nCLS\n.org 0x201"),
+        Err("Error line 3 : .org can't move back from 0x204 to 0x201 over emitted code".to_owned())
+    );
+}
+
+#[test]
+fn test_origin_and_padding_options() {
+    let options = Options {
+        origin: 0x600,
+        padding: 0xFF,
+        ..Options::default()
+    };
+
+    assert_eq!(
+        assemble_binary("JMP end\n.org 0x603\nend: DB 1", None, &options),
+        Ok(vec![0x16, 0x03, 0xFF, 0x01])
+    );
+}
+
+#[test]
+fn test_include() {
+    assert_eq!(
+        assemble_fixture("include_main.ch8asm", &Options::default()),
+        Ok(vec![0x12, 0x04, 0x20, 0x70, 0xA2, 0x02, 0xD0, 0x02])
+    );
+}
+
+#[test]
+fn test_include_search_path() {
+    let options = Options {
+        include_dirs: vec![fixture("sprites")],
+        ..Options::default()
+    };
+
+    assert_eq!(
+        assemble_fixture("include_search.ch8asm", &options),
+        Ok(vec![0x20, 0x70, 0xA2, 0x00])
+    );
+}
+
+#[test]
+fn test_include_cycle() {
+    assert_eq!(
+        assemble_fixture("include_cycle_a.ch8asm", &Options::default()),
+        Err(format!(
+            "Error in {} line 2 : Include cycle, '{}' is already being assembled",
+            fixture("include_cycle_b.ch8asm").display(),
+            fixture("include_cycle_a.ch8asm").display()
+        ))
+    );
+}
+
+#[test]
+fn test_include_error_location() {
+    assert_eq!(
+        assemble_fixture("include_error.ch8asm", &Options::default()),
+        Err(format!(
+            "Error in {} line 2 : Unknown instruction name",
+            fixture("sprites/broken.ch8asm").display()
+        ))
+    );
+}
+
+#[test]
+fn test_incbin() {
+    assert_eq!(
+        parse(&format!(
+            "LD I ship\nship: .incbin \"{}\"",
+            fixture("sprites/ship.bin").display()
+        )),
+        Ok(vec![0xA2, 0x02, 0x18, 0x3C, 0x7E, 0xFF])
+    );
+}
+
+#[test]
+fn test_incbin_offset_and_length() {
+    let path = fixture("sprites/ship.bin");
+
+    assert_eq!(
+        parse(&format!(".incbin \"{}\", 1, 2", path.display())),
+        Ok(vec![0x3C, 0x7E])
+    );
+    assert_eq!(
+        parse(&format!(".incbin \"{}\", 3, 2", path.display())),
+        Err(format!(
+            "Error line 1 : '{}' is only 4 bytes long",
+            path.display()
+        ))
+    );
+}
+
+#[test]
+fn test_incbin_overflows_memory() {
+    assert_eq!(
+        parse(&format!(
+            ".org 0xFFE\n.incbin \"{}\"",
+            fixture("sprites/ship.bin").display()
+        )),
+        Err(
+            "Error line 2 : 4 bytes at 0xFFE go past the end of the 4096 bytes of memory"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn test_macro() {
+    assert_eq!(
+        parse(".macro draw_digit digit, x, y\nLD V0 digit\nSPR V0\nLD V1 x\nLD V2 y\nDRW V1 V2 5\n.endm\ndraw_digit 3, 24, 4\ndraw_digit 0xA 16 16"),
+        Ok(vec![
+            0x60, 0x03, 0xF0, 0x29, 0x61, 0x18, 0x62, 0x04, 0xD1, 0x25, 0x60, 0x0A, 0xF0, 0x29,
+            0x61, 0x10, 0x62, 0x10, 0xD1, 0x25
+        ])
+    );
+}
+
+#[test]
+fn test_macro_local_labels() {
+    assert_eq!(
+        parse(".macro wait register\nloop: SNE register 0\nJMP done\nJMP loop\ndone:\n.endm\nwait V1\nwait V2"),
+        Ok(vec![
+            0x41, 0x00, 0x12, 0x06, 0x12, 0x00, 0x42, 0x00, 0x12, 0x0C, 0x12, 0x06
+        ])
+    );
+}
+
+#[test]
+fn test_nested_macros() {
+    assert_eq!(
+        parse(".macro set register, value\nLD register value\n.endm\n.macro clear2 a, b\nset a, 0\nset b, 0\n.endm\nclear2 V3, V4"),
+        Ok(vec![0x63, 0x00, 0x64, 0x00])
+    );
+}
+
+#[test]
+fn test_macro_errors() {
+    assert_eq!(
+        parse("CLS\n.macro bad value\nLD V1 value\n.endm\nbad 300"),
+        Err(
+            "Error line 3 in macro 'bad' called line 5 : Value 300 doesn't fit in nn (8 bits)"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse(".macro forever\nforever\n.endm\nforever"),
+        Err("Error line 2 in macro 'forever' called line 2 in macro 'forever' called line 2 ... in macro 'forever' called line 4 : Macro 'forever' is nested more than 64 expansions deep".to_owned())
+    );
+    assert_eq!(
+        parse("CLS\n.macro unfinished\nCLS"),
+        Err("Error line 2 : .macro without .endm".to_owned())
+    );
+}
+
+#[test]
+fn test_comparison_operators() {
+    assert_eq!(
+        parse("LD V1 (3 < 4) + (2 == 2) + !0 + (1 && 0) + (0 || 5)\nLD V2 !(4 >= 5) << 3"),
+        Ok(vec![0x61, 0x04, 0x62, 0x08])
+    );
+}
+
+#[test]
+fn test_conditionals() {
+    assert_eq!(
+        parse("LEVEL EQU 2\n.if LEVEL == 1\nLD V1 1\n.elif LEVEL == 2\nLD V1 2\n.else\nLD V1 3\n.endif"),
+        Ok(vec![0x61, 0x02])
+    );
+    assert_eq!(
+        parse(".if 0\n.if 1\nLD V1 1\n.else\nLD V1 2\n.endif\nbad line !\n.else\nCLS\n.endif"),
+        Ok(vec![0x00, 0xE0])
+    );
+    assert_eq!(
+        parse(".ifndef SPEED\n.define SPEED 3\n.endif\n.ifdef SPEED\nLD V1 SPEED\n.endif"),
+        Ok(vec![0x61, 0x03])
+    );
+}
+
+#[test]
+fn test_command_line_defines() {
+    let options = Options {
+        defines: vec![("DEBUG".to_owned(), 1), ("SPEED".to_owned(), -1)],
+        ..Options::default()
+    };
+
+    assert_eq!(
+        assemble_binary(".ifdef DEBUG\nLD V1 SPEED\n.endif", None, &options),
+        Ok(vec![0x61, 0xFF])
+    );
+    assert_eq!(
+        assemble_binary("DEBUG EQU 0", None, &options),
+        Err("Error line 1 : Constant 'DEBUG' is already defined on the command line".to_owned())
+    );
+}
+
+#[test]
+fn test_conditional_errors() {
+    assert_eq!(
+        parse("CLS\n.if 1\n.if 0\n.endif\nCLS"),
+        Err("Error line 2 : .if without .endif".to_owned())
+    );
+    assert_eq!(
+        parse(".if 1\n.else\n.elif 1\n.endif"),
+        Err("Error line 3 : .elif after .else".to_owned())
+    );
+    assert_eq!(
+        parse("CLS\n.endif"),
+        Err("Error line 2 : .endif without .if".to_owned())
+    );
+    assert_eq!(
+        parse(".if LATER\nlater:\n.endif"),
+        Err("Error line 1 : Undefined symbol 'LATER'".to_owned())
+    );
+}
+
+#[test]
+fn test_runtime_conditionals() {
+    assert_eq!(
+        parse(".if V1 == 5\nLD V2 1\n.else\nLD V2 2\n.endif"),
+        Ok(vec![
+            0x31, 0x05, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02
+        ])
+    );
+    assert_eq!(
+        parse(".if V1 != V2\nCLS\n.endif\n.if !key V3\nRET\n.endif\n.if KEY v3\nCLS\n.endif"),
+        Ok(vec![
+            0x91, 0x20, 0x12, 0x06, 0x00, 0xE0, 0xE3, 0xA1, 0x12, 0x0C, 0x00, 0xEE, 0xE3, 0x9E,
+            0x12, 0x12, 0x00, 0xE0
+        ])
+    );
+    assert_eq!(
+        parse(".while V2 != 0\n.if V2 == 3\nCLS\n.endif\nADD V2 -1\n.endwhile\nRET"),
+        Ok(vec![
+            0x42, 0x00, 0x12, 0x0E, 0x32, 0x03, 0x12, 0x0A, 0x00, 0xE0, 0x72, 0xFF, 0x12, 0x00,
+            0x00, 0xEE
+        ])
+    );
+    assert_eq!(
+        parse(".if 0\n.if V1 == 1\nCLS\n.else\nRET\n.endif\n.while V1 == 1\n.endwhile\n.endif"),
+        Ok(vec![])
+    );
+}
+
+#[test]
+fn test_runtime_conditional_errors() {
+    assert_eq!(
+        parse(".if V1 < 3\n.endif"),
+        Err("Error line 1 : Condition on registers needs ==, != or key".to_owned())
+    );
+    assert_eq!(
+        parse(".while DT == 0\n.endwhile"),
+        Err("Error line 1 : Condition must start with a V register".to_owned())
+    );
+    assert_eq!(
+        parse(".if V1 == 1\nCLS\n.elif V1 == 2\n.endif"),
+        Err(
+            "Error line 3 : .elif can't follow an .if on registers or keys, nest an .if in .else"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse(".while V1 == 1\nCLS"),
+        Err("Error line 1 : .while without .endwhile".to_owned())
+    );
+    assert_eq!(
+        parse("CLS\n.endwhile"),
+        Err("Error line 2 : .endwhile without .while".to_owned())
+    );
+}
+
+#[test]
+fn test_rept() {
+    assert_eq!(
+        parse(".rept 3, i\nDB i * 2\n.endr\n.rept 2\nCLS\n.endr\n.rept 0\nbad line !\n.endr"),
+        Ok(vec![0x00, 0x02, 0x04, 0x00, 0xE0, 0x00, 0xE0])
+    );
+    assert_eq!(
+        parse(".rept 2, row\n.rept 2, column\nDB row * 16 + column\n.endr\n.endr"),
+        Ok(vec![0x00, 0x01, 0x10, 0x11])
+    );
+}
+
+#[test]
+fn test_rept_local_labels() {
+    assert_eq!(
+        parse(".rept 2\nskip: JMP skip\n.endr"),
+        Ok(vec![0x12, 0x00, 0x12, 0x02])
+    );
+}
+
+#[test]
+fn test_rept_errors() {
+    assert_eq!(
+        parse(".rept 3, i\nLD V1 i * 150\n.endr"),
+        Err(
+            "Error line 2 in iteration 2 of .rept line 1 : Value 300 doesn't fit in nn (8 bits)"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse("CLS\n.rept 2\nCLS"),
+        Err("Error line 2 : .rept without .endr".to_owned())
+    );
+    assert_eq!(
+        parse(".rept -1\n.endr"),
+        Err("Error line 1 : .rept count -1 is negative".to_owned())
+    );
+}
+
+#[test]
+fn test_local_labels() {
+    assert_eq!(
+        parse("first:\n.loop: JMP .loop\nsecond:\n.loop: JMP .loop\nJMP first.loop"),
+        Ok(vec![0x12, 0x00, 0x12, 0x02, 0x12, 0x00])
+    );
+    assert_eq!(
+        parse("main: JMP .end\nCLS\n.end: RET"),
+        Ok(vec![0x12, 0x04, 0x00, 0xE0, 0x00, 0xEE])
+    );
+    assert_eq!(
+        parse(".loop: JMP .loop"),
+        Err("Error line 1 : Local label '.loop' has no global label before it".to_owned())
+    );
+}
+
+#[test]
+fn test_scopes() {
+    assert_eq!(
+        parse(".scope player\nX EQU 5\nstart: LD V1 X\nJMP start\n.endscope\n.scope enemy\nX EQU 7\nstart: LD V1 X\n.endscope\nLD V2 player.X + enemy.X\nJMP enemy.start"),
+        Ok(vec![0x61, 0x05, 0x12, 0x00, 0x61, 0x07, 0x62, 0x0C, 0x12, 0x04])
+    );
+    assert_eq!(
+        parse("SPEED EQU 2\n.scope outer\n.scope inner\nLD V1 SPEED + LATER\n.endscope\nLATER EQU 1\n.endscope"),
+        Ok(vec![0x61, 0x03])
+    );
+}
+
+#[test]
+fn test_scope_errors() {
+    assert_eq!(
+        parse(".scope a\nX EQU 1\n.endscope\nLD V1 X"),
+        Err("Error line 4 : Undefined symbol 'X'".to_owned())
+    );
+    assert_eq!(
+        parse("CLS\n.scope a\nCLS"),
+        Err("Error line 2 : .scope without .endscope".to_owned())
+    );
+    assert_eq!(
+        parse(".scope a\nx: CLS\nx: CLS\n.endscope"),
+        Err("Error line 3 : Label 'a.x' is already defined line 2".to_owned())
+    );
+}
+
+#[test]
+fn test_sprite() {
+    assert_eq!(
+        parse("LD I arrow\nDRW V1 V2 arrow.height\n.sprite arrow\n\"..#..\"\n\".###.\" ; body\n\n\"00100\"\n.endsprite"),
+        Ok(vec![0xA2, 0x04, 0xD1, 0x23, 0x20, 0x70, 0x20])
+    );
+    assert_eq!(
+        parse(&format!(
+            "DRW V1 V2 wide.height\n.sprite wide, 16\n\"#..............#\"\n{}.endsprite",
+            "\"#\"\n".repeat(15)
+        ))
+        .map(|binary| binary[..8].to_vec()),
+        Ok(vec![0xD1, 0x20, 0x80, 0x01, 0x80, 0x00, 0x80, 0x00])
+    );
+}
+
+#[test]
+fn test_sprite_errors() {
+    assert_eq!(
+        parse(".sprite s\n\"#.#\"\n\"#.x\"\n.endsprite"),
+        Err("Error line 3 : Sprite pixel 'x' must be # or . (1 or 0)".to_owned())
+    );
+    assert_eq!(
+        parse(".sprite s\n\"#........\"\n\"#\"\n.endsprite"),
+        Err("Error line 2 : Sprite row \"#........\" is wider than 8 pixels".to_owned())
+    );
+    assert_eq!(
+        parse(".sprite s, 16\n\"#................\"\n.endsprite"),
+        Err("Error line 2 : Sprite row \"#................\" is wider than 16 pixels".to_owned())
+    );
+    assert_eq!(
+        parse(".sprite s, 16\n\"#...............\"\n\"#\"\n.endsprite"),
+        Err("Error line 1 : Sprite 's' has 2 rows, a 16x16 sprite needs 16".to_owned())
+    );
+    assert_eq!(
+        parse(".sprite s, 12\n\"#\"\n.endsprite"),
+        Err("Error line 1 : .sprite needs a name, and 16 for a 16x16 sprite".to_owned())
+    );
+    assert_eq!(
+        parse("CLS\n.sprite s\n.endsprite"),
+        Err("Error line 2 : Sprite 's' has no rows".to_owned())
+    );
+}
+
+#[test]
+fn test_text() {
+    assert_eq!(
+        parse(".charmap \"0123456789\", 0\n.charmap \"ABC\", 10\n.charmap \" \", blank - font\nLD V1 message.length\nmessage: .text \"CAB 90\"\nfont: DB 0\nblank: DB 0"),
+        Ok(vec![0x61, 0x06, 0x0C, 0x0A, 0x0B, 0x01, 0x09, 0x00, 0x00, 0x00])
+    );
+}
+
+#[test]
+fn test_text_errors() {
+    assert_eq!(
+        parse(".charmap \"AB\", 1\n.text \"ABC\""),
+        Err("Error line 2 : Character 'C' has no .charmap value".to_owned())
+    );
+    assert_eq!(
+        parse(".charmap \"AB\", 255\n.text \"AB\""),
+        Err("Error line 2 : Value 256 doesn't fit in a byte".to_owned())
+    );
+}
+
+#[test]
+fn test_align_and_fill() {
+    assert_eq!(
+        parse("CLS\nDB 1\n.align 4\ntable: .fill 3, 0xFF\n.fill 1\n.align 1\nLD I table"),
+        Ok(vec![
+            0x00, 0xE0, 0x01, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0xA2, 0x04
+        ])
+    );
+    assert_eq!(parse(".align 3"), Ok(vec![0x00]));
+}
+
+#[test]
+fn test_space() {
+    let options = Options {
+        padding: 0xAA,
+        ..Options::default()
+    };
+
+    assert_eq!(
+        assemble_binary(
+            "LD I buffer\nBCD V1\nbuffer: .space 3\nscore: .space 1",
+            None,
+            &options
+        ),
+        Ok(vec![0xA2, 0x04, 0xF1, 0x33])
+    );
+    assert_eq!(
+        assemble_binary("LD I score\nbuffer: .space 3\nscore: DB 5", None, &options),
+        Ok(vec![0xA2, 0x05, 0xAA, 0xAA, 0xAA, 0x05])
+    );
+}
+
+#[test]
+fn test_reservation_errors() {
+    assert_eq!(
+        parse(".align 0"),
+        Err("Error line 1 : .align 0 isn't between 1 and 4096 bytes".to_owned())
+    );
+    assert_eq!(
+        parse(".fill 2, 256"),
+        Err("Error line 1 : Value 256 doesn't fit in a byte".to_owned())
+    );
+    assert_eq!(
+        parse(".fill 3 -1"),
+        Err("Error line 1 : '-' after a space is ambiguous between two operands, separate them with commas or write 'a - b'".to_owned())
+    );
+    assert_eq!(
+        parse("CLS\n.space 0xE00"),
+        Err(
+            "Error line 2 : 3584 bytes at 0x202 go past the end of the 4096 bytes of memory"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn test_cowgod_dialect() {
+    assert_eq!(
+        parse(".dialect cowgod\nSE V1, 5\nJP V0, 0x300\nLD F, V2\nLD B, V3\nLD [I], V4\nLD V5, [I]\nLD V6, K\nSHR V7\nLD I, 0x234\nJP 0x200"),
+        Ok(vec![
+            0x31, 0x05, 0xB3, 0x00, 0xF2, 0x29, 0xF3, 0x33, 0xF4, 0x55, 0xF5, 0x65, 0xF6, 0x0A,
+            0x87, 0x76, 0xA2, 0x34, 0x12, 0x00
+        ])
+    );
+
+    let options = Options {
+        dialect: Dialect::Cowgod,
+        ..Options::default()
+    };
+
+    assert_eq!(
+        assemble_binary("loop: SE V1 V2\nJP loop", None, &options),
+        Ok(vec![0x51, 0x20, 0x12, 0x00])
+    );
+    assert_eq!(
+        assemble_binary("JMP 0x200", None, &options),
+        Err("Error line 1 : Unknown instruction name".to_owned())
+    );
+}
+
+#[test]
+fn test_dialect_of_included_file() {
+    assert_eq!(
+        assemble_fixture("include_cowgod.ch8asm", &Options::default()),
+        Ok(vec![0x31, 0x01, 0x31, 0x02])
+    );
+}
+
+#[test]
+fn test_octo() {
+    assert_eq!(
+        assemble_fixture("octo.8o", &Options::default()),
+        Ok(vec![
+            0x00, 0xE0, 0x60, 0x00, 0x61, 0x00, 0x70, 0x01, 0x40, 0x03, 0x61, 0x07, 0x40, 0x05,
+            0x12, 0x12, 0x12, 0x06, 0x31, 0x07, 0x12, 0x1C, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x1E,
+            0x22, 0x20, 0x12, 0x1E, 0xF2, 0x0A, 0xE2, 0x9E, 0x00, 0xEE, 0x12, 0xFF
+        ])
+    );
+    assert_eq!(
+        assemble_fixture("include_octo.ch8asm", &Options::default()),
+        Ok(vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xE0, 0x00, 0xEE])
+    );
+    assert_eq!(
+        assemble_fixture("octo_main_after_data.8o", &Options::default()),
+        Ok(vec![
+            0x12, 0x0A, 0xFF, 0x81, 0x70, 0xFB, 0x80, 0x15, 0x00, 0xEE, 0x22, 0x04
+        ])
+    );
+}
+
+#[test]
+fn test_octo_errors() {
+    let octo_path = Some(Path::new("game.8o"));
+
+    assert_eq!(
+        assemble_binary(": main\nloop\nv0 += 1\n", octo_path, &Options::default()),
+        Err("Error line 2 : 'loop' without 'again'".to_owned())
+    );
+    assert_eq!(
+        assemble_binary(
+            ": main\nif v0 > 3 then v1 := 2",
+            octo_path,
+            &Options::default()
+        ),
+        Err("Error line 2 : Comparison '>' isn't supported".to_owned())
+    );
+    assert_eq!(
+        assemble_binary(": main v0 := 300", octo_path, &Options::default()),
+        Err("Error line 1 : Value 300 doesn't fit in nn (8 bits)".to_owned())
+    );
+    assert_eq!(
+        assemble_binary(": draw clear ;", octo_path, &Options::default()),
+        Err("Error line 1 : Octo program has no ': main' to start from".to_owned())
+    );
+}
+
+#[test]
+fn test_hexadecimal_registers() {
+    assert_eq!(
+        parse("LD VA 1\nADD VF VB\nSEQ vc, ve"),
+        Ok(vec![0x6A, 0x01, 0x8F, 0xB4, 0x5C, 0xE0])
+    );
+    assert_eq!(
+        parse("LD VG 1"),
+        Err("Error line 1 : Undefined symbol 'VG'".to_owned())
+    );
+    // Only V0 to V15 and VA to VF are registers, other words starting with v are symbols
+    assert_eq!(
+        parse("v2speed EQU 3\nLD I v1_table\nLD V1 v2speed\nv1_table: DB 1"),
+        Ok(vec![0xA2, 0x04, 0x61, 0x03, 0x01])
+    );
+}
+
+#[test]
+fn test_case_insensitive() {
+    assert_eq!(
+        parse("ld v1 5B\nLd i, 0x300\ndb 1\nadd dt, v1"),
+        Err("Error line 4 : ADD first parameter must be V[n] or I".to_owned())
+    );
+    assert_eq!(
+        parse("ld v1 5B\nLd i, 0x300\ndb 1\nld dt, v1\nsne vF, 0"),
+        Ok(vec![0x61, 0x05, 0xA3, 0x00, 0x01, 0xF1, 0x15, 0x4F, 0x00])
+    );
+}
+
+#[test]
+fn test_register_named_symbols() {
+    // I, DT and ST are registers only as a whole operand of an instruction
+    assert_eq!(
+        parse("i: CLS\ndt EQU 2\nDB dt, i >> 4\nLD I, 0x300\nLD DT, V1"),
+        Ok(vec![0x00, 0xE0, 0x02, 0x20, 0xA3, 0x00, 0xF1, 0x15])
+    );
+    assert_eq!(
+        parse("i: JMP i"),
+        Err("Error line 1 : JMP first parameter must be Address".to_owned())
+    );
+}
+
+#[test]
+fn test_strict_casing() {
+    let options = Options {
+        strict: true,
+        ..Options::default()
+    };
+    let warnings = |text| assemble(text, None, &options).map(|assembly| assembly.warnings);
+
+    assert_eq!(warnings("CLS\n.byte 1\nLD V1 V2"), Ok(vec![]));
+    assert_eq!(
+        warnings("CLS\nld V1 5\nLD v2 6\nDb 1"),
+        Ok(vec![
+            "Warning line 2 : 'ld' is in lowercase but this file is in uppercase since line 1"
+                .to_owned(),
+            "Warning line 3 : 'v2' is in lowercase but this file is in uppercase since line 1"
+                .to_owned(),
+            "Warning line 4 : 'Db' mixes uppercase and lowercase".to_owned(),
+        ])
+    );
+    assert_eq!(
+        assemble("ld v1 5\nLD V1 6", None, &Options::default()).map(|assembly| assembly.warnings),
+        Ok(vec![])
+    );
+}
+
+#[test]
+fn test_sys() {
+    assert_eq!(
+        assemble("SYS 0x123\nCLS\nsys routine\nroutine: RET", None, &Options::default())
+            .map(|assembly| (assembly.binary, assembly.warnings)),
+        Ok((
+            vec![0x01, 0x23, 0x00, 0xE0, 0x02, 0x06, 0x00, 0xEE],
+            vec![
+                "Warning line 1 : SYS runs machine code of the COSMAC VIP, most interpreters ignore it".to_owned(),
+                "Warning line 3 : SYS runs machine code of the COSMAC VIP, most interpreters ignore it".to_owned(),
+            ]
+        ))
+    );
+}
+
+#[test]
+fn test_opcode() {
+    assert_eq!(
+        parse(".opcode 0x00FF\n.opcode 0xF000 | hi(target)\ntarget: .opcode 0xFFFF"),
+        Ok(vec![0x00, 0xFF, 0xF0, 0x02, 0xFF, 0xFF])
+    );
+    assert_eq!(
+        parse(".opcode 0x10000"),
+        Err("Error line 1 : Value 65536 doesn't fit in a word".to_owned())
+    );
+}
+
+#[test]
+fn test_pseudo_instructions() {
+    assert_eq!(
+        parse("SGT V1, V2\nSLT V1 V2\nNOT V3\nNEG V3"),
+        Ok(vec![
+            0x82, 0x15, 0x82, 0x14, 0x4F, 0x00, 0x81, 0x25, 0x81, 0x24, 0x4F, 0x00, 0x6F, 0xFF,
+            0x83, 0xF3, 0x6F, 0x00, 0x83, 0xF7
+        ])
+    );
+    assert_eq!(
+        parse("ADD16 V2, V4\nADD16 V2, 0x1234\nLD I table + V5\ntable: DB 0"),
+        Ok(vec![
+            0x82, 0x44, 0x83, 0x54, 0x82, 0xF4, 0x72, 0x12, 0x6F, 0x34, 0x83, 0xF4, 0x82, 0xF4,
+            0xA2, 0x12, 0xF5, 0x1E, 0x00
+        ])
+    );
+}
+
+#[test]
+fn test_pseudo_instruction_errors() {
+    assert_eq!(
+        parse("NOT VF"),
+        Err("Error line 1 : NOT clobbers VF, it can't be an operand".to_owned())
+    );
+    assert_eq!(
+        parse("SGT V1, V1"),
+        Err("Error line 1 : SGT needs two different registers".to_owned())
+    );
+    assert_eq!(
+        parse("ADD16 V2, V3"),
+        Err("Error line 1 : ADD16 register pairs can't overlap".to_owned())
+    );
+    assert_eq!(
+        parse("ADD16 VE, 1"),
+        Err("Error line 1 : ADD16 register pairs can't end with VF".to_owned())
+    );
+    assert_eq!(
+        parse("SLT V1"),
+        Err("Error line 1 : SLT takes Vx, Vy, and clobbers VF".to_owned())
+    );
+    assert_eq!(
+        assemble("SEQ V1 0\nNEG V1", None, &Options::default()).map(|assembly| assembly.warnings),
+        Ok(vec![
+            "Warning line 2 : NEG expands to several opcodes, SEQ only skips the first one"
+                .to_owned()
+        ])
+    );
+}
+
+#[test]
+fn test_listing() {
+    assert_eq!(
+        assemble(
+            "loop: NOT V1\nJMP loop\n.space 4\nDB 1, 2",
+            None,
+            &Options::default()
+        )
+        .map(|assembly| assembly.listing),
+        Ok(vec![
+            "200  6F FF                        LD VF, 255                       ; line 1"
+                .to_owned(),
+            "202  81 F3                        XOR V1, VF                       ; line 1"
+                .to_owned(),
+            "204  12 00                        JMP loop                         ; line 2"
+                .to_owned(),
+            "206  00 00 00 00                  .space 4                         ; line 3"
+                .to_owned(),
+            "20A  01 02                        .byte 1, 2                       ; line 4"
+                .to_owned(),
+        ])
+    );
+}
+
+#[test]
+fn test_aliases() {
+    assert_eq!(
+        parse(".alias player_x V3\n.reg speed, VC\nADD player_x speed\nLD player_x, 5"),
+        Ok(vec![0x83, 0xC4, 0x63, 0x05])
+    );
+    assert_eq!(
+        parse(".scope a\n.alias x V1\nLD x 1\n.endscope\n.scope b\n.alias x V2\nLD x 2\n.endscope"),
+        Ok(vec![0x61, 0x01, 0x62, 0x02])
+    );
+    assert_eq!(
+        parse(".alias x V1\n.if x == 1\nCLS\n.endif"),
+        Ok(vec![0x31, 0x01, 0x12, 0x06, 0x00, 0xE0])
+    );
+    assert_eq!(
+        assemble(
+            ".alias a V1\n.alias b a\n.scope inner\n.alias c V1\n.endscope",
+            None,
+            &Options::default()
+        )
+        .map(|assembly| assembly.warnings),
+        Ok(vec![
+            "Warning line 2 : 'b' names V1 like 'a' defined line 1".to_owned()
+        ])
+    );
+}
+
+#[test]
+fn test_alias_errors() {
+    assert_eq!(
+        parse(".alias x V1\nDB x"),
+        Err("Error line 2 : Register alias 'x' can't be used in an expression".to_owned())
+    );
+    assert_eq!(
+        parse(".alias x 5"),
+        Err("Error line 1 : .alias needs a V register, not '5'".to_owned())
+    );
+    assert_eq!(
+        parse(".reg V1 V2"),
+        Err("Error line 1 : Invalid alias name 'V1'".to_owned())
+    );
+    assert_eq!(
+        parse(".alias x V1\nx: CLS"),
+        Err("Error line 2 : Label 'x' is already defined line 1".to_owned())
+    );
+}
+
+#[test]
+fn test_variables() {
+    assert_eq!(
+        assemble(
+            ".var score 2\nLD I score\n.var lives, 1\nLD I lives\n.space 4",
+            None,
+            &Options::default()
+        )
+        .map(|assembly| (assembly.binary, assembly.ram_start, assembly.ram_size)),
+        Ok((vec![0xA2, 0x08, 0xA2, 0x0A], 0x208, 3))
+    );
+    assert_eq!(
+        parse(".var buffer 0xDFE\nCLS\n.var overflow 1"),
+        Err(
+            "Error line 3 : Variable 'overflow' goes past the end of the 4096 bytes of memory"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse(".var buffer 0xDFE\nCLS\nCLS"),
+        Err("Error line 1 : Variable 'buffer' at 0x204 goes past the end of the 4096 bytes of memory".to_owned())
+    );
+    assert_eq!(
+        parse(".var x 1\n.org x"),
+        Err("Error line 2 : Variable 'x' has no address before the end of the program".to_owned())
+    );
+    assert_eq!(
+        parse(".var x 0"),
+        Err("Error line 1 : .var size 0 isn't between 1 and 4096 bytes".to_owned())
+    );
+}
+
+#[test]
+fn test_sections() {
+    assert_eq!(
+        assemble(
+            "CLS\n.section data\nship: DB 0xF0\n.section code\nLD I ship\nRET",
+            None,
+            &Options::default()
+        )
+        .map(|assembly| (assembly.binary, assembly.sections)),
+        Ok((
+            vec![0x00, 0xE0, 0xA2, 0x06, 0x00, 0xEE, 0xF0],
+            vec![("code".to_owned(), 0x200, 6), ("data".to_owned(), 0x206, 1)]
+        ))
+    );
+
+    let options = Options {
+        sections: vec!["data".to_owned(), "code".to_owned()],
+        ..Options::default()
+    };
+
+    assert_eq!(
+        assemble_binary(
+            ".align 2\nJMP start\n.section data\nvalue: DB 7\n.section code\nstart: LD I value",
+            None,
+            &options
+        ),
+        Ok(vec![0x07, 0x00, 0x12, 0x04, 0xA2, 0x00])
+    );
+    assert_eq!(
+        assemble(
+            ".section bss\nbuffer: .space 16\n.section code\nLD I buffer\nLD I score\n.var score 1",
+            None,
+            &Options::default()
+        )
+        .map(|assembly| (assembly.binary, assembly.ram_start)),
+        Ok((vec![0xA2, 0x04, 0xA2, 0x14], 0x214))
+    );
+}
+
+#[test]
+fn test_section_errors() {
+    assert_eq!(
+        parse(".section bss\nDB 1"),
+        Err("Error line 2 : Section 'bss' can only reserve memory with .space".to_owned())
+    );
+    assert_eq!(
+        parse(".section data\n.org 0x300"),
+        Err(
+            "Error line 2 : .org can't be used in section 'data', only in the section placed first"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        parse(".section data\nx: DB 1\n.section code\n.if x\n.endif"),
+        Err("Error line 4 : Label 'x' has no address before the sections are placed".to_owned())
+    );
+    assert_eq!(
+        parse(".section"),
+        Err("Error line 1 : .section needs a name".to_owned())
+    );
 }